@@ -0,0 +1,402 @@
+//! Strategies for moving files, selected with `--backend`.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use anyhow::bail;
+
+use crate::Rename;
+
+/// Moves single files or directories, and creates and removes the parent
+/// directories needed for that.
+pub trait RenameBackend {
+    /// Move `from` to `to`, which does not exist.
+    fn rename(&mut self, from: &Path, to: &Path) -> anyhow::Result<()>;
+
+    /// Whether missing parent directories are created before renaming.
+    fn creates_dirs(&self) -> bool {
+        true
+    }
+
+    fn create_dir(&mut self, dir: &Path) -> io::Result<()> {
+        fs::create_dir(dir)
+    }
+
+    /// Remove a directory created by [`RenameBackend::create_dir`] again, if it
+    /// is empty.
+    fn remove_dir(&mut self, dir: &Path) -> io::Result<()> {
+        fs::remove_dir(dir)
+    }
+
+    /// Problems that did not stop a rename, like an original that could not
+    /// be deleted after copying it. Taking them clears the list.
+    fn take_warnings(&mut self) -> Vec<String> {
+        vec![]
+    }
+
+    /// The steps taken so far if they are only recorded, without touching
+    /// any files.
+    fn recorded_steps(&self) -> Option<&[Rename]> {
+        None
+    }
+}
+
+/// The built-in backends.
+#[cfg_attr(feature = "cli", derive(clap::ArgEnum))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackendKind {
+    /// Rename within the file system, or copy and delete across file systems
+    Fs,
+    /// Copy and delete, which also works across file systems
+    Copy,
+    /// Run `git mv`
+    Git,
+    /// Run `hg mv`
+    Hg,
+    /// Run `svn mv`
+    Svn,
+    /// Do not touch any files, only record the steps
+    Record,
+}
+
+impl BackendKind {
+    /// The command for command-based backends, as a shell command line.
+    pub fn command(self) -> Option<&'static str> {
+        match self {
+            BackendKind::Git => Some("git mv --"),
+            BackendKind::Hg => Some("hg mv --"),
+            BackendKind::Svn => Some("svn mv --"),
+            BackendKind::Fs | BackendKind::Copy | BackendKind::Record => None,
+        }
+    }
+
+    /// Create the backend, which reports the progress of copying large files
+    /// if given.
+    pub fn create(self, progress: Option<Progress>) -> Box<dyn RenameBackend> {
+        match self {
+            BackendKind::Fs => Box::new(FsBackend {
+                progress,
+                warnings: vec![],
+            }),
+            BackendKind::Copy => Box::new(CopyBackend {
+                progress,
+                warnings: vec![],
+            }),
+            BackendKind::Record => Box::new(RecordingBackend::default()),
+            kind => Box::new(
+                CommandBackend::new(kind.command().expect("command backend"))
+                    .expect("built-in commands parse"),
+            ),
+        }
+    }
+}
+
+/// Reports the progress of copying a large file, with the path being copied,
+/// the bytes copied so far and the size of the file.
+pub type Progress = Box<dyn FnMut(&Path, u64, u64)>;
+
+/// Files at least this large report their progress while being copied.
+const LARGE_FILE: u64 = 64 * 1024 * 1024;
+
+/// Renames with [`fs::rename`], falling back to copying and deleting when
+/// moving to another file system.
+#[derive(Default)]
+pub struct FsBackend {
+    pub progress: Option<Progress>,
+    warnings: Vec<String>,
+}
+
+impl FsBackend {
+    /// Move with `rename`, falling back to copying and deleting if it fails
+    /// because `to` is on another file system.
+    fn rename_with(
+        &mut self,
+        from: &Path,
+        to: &Path,
+        rename: impl FnOnce(&Path, &Path) -> io::Result<()>,
+    ) -> anyhow::Result<()> {
+        match rename(from, to) {
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+                let warning = copy_and_delete(from, to, &mut self.progress)?;
+                self.warnings.extend(warning);
+            }
+            result => result?,
+        }
+        Ok(())
+    }
+}
+
+impl RenameBackend for FsBackend {
+    fn rename(&mut self, from: &Path, to: &Path) -> anyhow::Result<()> {
+        self.rename_with(from, to, |from, to| fs::rename(from, to))
+    }
+
+    fn take_warnings(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.warnings)
+    }
+}
+
+/// Copies files and deletes the originals afterwards.
+#[derive(Default)]
+pub struct CopyBackend {
+    pub progress: Option<Progress>,
+    warnings: Vec<String>,
+}
+
+impl RenameBackend for CopyBackend {
+    fn rename(&mut self, from: &Path, to: &Path) -> anyhow::Result<()> {
+        let warning = copy_and_delete(from, to, &mut self.progress)?;
+        self.warnings.extend(warning);
+        Ok(())
+    }
+
+    fn take_warnings(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.warnings)
+    }
+}
+
+/// Copy `from` to `to` recursively, and delete `from` once everything has been
+/// copied.
+///
+/// Symbolic links are copied as they are. Permissions, timestamps and, on
+/// Unix, extended attributes are preserved where the file systems allow it. A
+/// partial copy is removed again if copying fails.
+///
+/// Once the copy is complete the rename counts as done, even if `from` cannot
+/// be deleted, since it may already be partly gone. A warning about the
+/// leftover is returned then.
+pub fn copy_and_delete(
+    from: &Path,
+    to: &Path,
+    progress: &mut Option<Progress>,
+) -> io::Result<Option<String>> {
+    if let Err(e) = copy_recursively(from, to, progress) {
+        if to.symlink_metadata().is_ok_and(|m| m.is_dir()) {
+            let _ = fs::remove_dir_all(to);
+        } else {
+            let _ = fs::remove_file(to);
+        }
+        return Err(e);
+    }
+
+    let removed = if from.symlink_metadata()?.is_dir() {
+        fs::remove_dir_all(from)
+    } else {
+        fs::remove_file(from)
+    };
+    Ok(removed.err().map(|e| {
+        format!(
+            "copied {} to {}, but could not delete the original: {}",
+            from.display(),
+            to.display(),
+            e
+        )
+    }))
+}
+
+fn copy_recursively(from: &Path, to: &Path, progress: &mut Option<Progress>) -> io::Result<()> {
+    let metadata = from.symlink_metadata()?;
+    if metadata.file_type().is_symlink() {
+        let target = fs::read_link(from)?;
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(target, to)?;
+        #[cfg(windows)]
+        {
+            if from.is_dir() {
+                std::os::windows::fs::symlink_dir(target, to)?;
+            } else {
+                std::os::windows::fs::symlink_file(target, to)?;
+            }
+        }
+        return Ok(());
+    }
+
+    if metadata.is_dir() {
+        fs::create_dir(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_recursively(&entry.path(), &to.join(entry.file_name()), progress)?;
+        }
+    } else {
+        match progress {
+            Some(progress) if metadata.len() >= LARGE_FILE => {
+                copy_file_with_progress(from, to, metadata.len(), progress)?
+            }
+            _ => {
+                fs::copy(from, to)?;
+            }
+        }
+    }
+    copy_metadata(from, to, &metadata)
+}
+
+fn copy_file_with_progress(
+    from: &Path,
+    to: &Path,
+    size: u64,
+    progress: &mut Progress,
+) -> io::Result<()> {
+    let mut reader = fs::File::open(from)?;
+    let mut writer = fs::File::create(to)?;
+    let mut buffer = vec![0; 1024 * 1024];
+    let mut copied = 0;
+    progress(from, 0, size);
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        writer.write_all(&buffer[..n])?;
+        copied += n as u64;
+        progress(from, copied, size);
+    }
+    writer.flush()
+}
+
+/// Copy extended attributes, timestamps and permissions, in that order so
+/// that read-only files can still be updated. Extended attributes and
+/// timestamps are skipped where they are not supported.
+fn copy_metadata(from: &Path, to: &Path, metadata: &fs::Metadata) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let writable = metadata.permissions().mode() | 0o200;
+        fs::set_permissions(to, fs::Permissions::from_mode(writable))?;
+        if let Ok(names) = xattr::list(from) {
+            for name in names {
+                if let Ok(Some(value)) = xattr::get(from, &name) {
+                    let _ = xattr::set(to, &name, &value);
+                }
+            }
+        }
+    }
+
+    if let (Ok(accessed), Ok(modified)) = (metadata.accessed(), metadata.modified()) {
+        let times = fs::FileTimes::new()
+            .set_accessed(accessed)
+            .set_modified(modified);
+        let _ = fs::File::open(to).and_then(|f| f.set_times(times));
+    }
+
+    fs::set_permissions(to, metadata.permissions())
+}
+
+/// Runs a command with the original and new path appended, like
+/// `--rename-command` or `git mv`.
+#[derive(Debug, Clone)]
+pub struct CommandBackend {
+    command: Vec<String>,
+}
+
+impl CommandBackend {
+    pub fn new(command: &str) -> anyhow::Result<Self> {
+        let command = shell_words::split(command)?;
+        if command.is_empty() {
+            bail!("Rename command is empty");
+        }
+        Ok(CommandBackend { command })
+    }
+}
+
+impl RenameBackend for CommandBackend {
+    fn rename(&mut self, from: &Path, to: &Path) -> anyhow::Result<()> {
+        let status = subprocess::Exec::cmd(&self.command[0])
+            .args(&self.command[1..])
+            .arg(from)
+            .arg(to)
+            .join()?;
+        if !status.success() {
+            bail!("Rename command exited with {:?}", status);
+        }
+        Ok(())
+    }
+
+    // The command takes care of the file system.
+    fn creates_dirs(&self) -> bool {
+        false
+    }
+}
+
+/// Records the steps without touching any files.
+#[derive(Debug, Default, Clone)]
+pub struct RecordingBackend {
+    pub steps: Vec<Rename>,
+}
+
+impl RenameBackend for RecordingBackend {
+    fn rename(&mut self, from: &Path, to: &Path) -> anyhow::Result<()> {
+        self.steps.push(Rename {
+            original: from.into(),
+            new: to.into(),
+        });
+        Ok(())
+    }
+
+    fn creates_dirs(&self) -> bool {
+        false
+    }
+
+    fn recorded_steps(&self) -> Option<&[Rename]> {
+        Some(&self.steps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copy_and_delete_keeps_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let (from, to) = (dir.path().join("from"), dir.path().join("to"));
+        fs::create_dir_all(from.join("sub")).unwrap();
+        fs::write(from.join("sub/file"), "content").unwrap();
+        let modified = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+        let file = fs::File::open(from.join("sub/file")).unwrap();
+        file.set_modified(modified).unwrap();
+        let mut readonly = file.metadata().unwrap().permissions();
+        readonly.set_readonly(true);
+        fs::set_permissions(from.join("sub/file"), readonly).unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("sub/file", from.join("link")).unwrap();
+
+        let mut backend = CopyBackend::default();
+        backend.rename(&from, &to).unwrap();
+        assert!(backend.take_warnings().is_empty());
+        assert!(!from.exists());
+        assert_eq!(fs::read_to_string(to.join("sub/file")).unwrap(), "content");
+        let metadata = to.join("sub/file").metadata().unwrap();
+        assert!(metadata.permissions().readonly());
+        assert_eq!(metadata.modified().unwrap(), modified);
+        #[cfg(unix)]
+        assert_eq!(
+            fs::read_link(to.join("link")).unwrap(),
+            Path::new("sub/file")
+        );
+    }
+
+    #[test]
+    fn fs_backend_copies_across_file_systems() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (from, to) = (dir.path().join("from"), dir.path().join("to"));
+        fs::create_dir(&from)?;
+        fs::write(from.join("file"), "content")?;
+
+        let mut backend = FsBackend::default();
+        backend.rename_with(&from, &to, |_, _| {
+            Err(io::Error::from(io::ErrorKind::CrossesDevices))
+        })?;
+        assert!(!from.exists());
+        assert_eq!(fs::read_to_string(to.join("file"))?, "content");
+
+        // Other errors are not retried by copying.
+        fs::write(&from, "")?;
+        let denied = backend.rename_with(&from, &dir.path().join("other"), |_, _| {
+            Err(io::Error::from(io::ErrorKind::PermissionDenied))
+        });
+        assert!(denied.is_err());
+        assert!(from.exists());
+        Ok(())
+    }
+}
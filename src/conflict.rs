@@ -0,0 +1,245 @@
+//! Resolving renames onto files that already exist.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::output::{Event, Events};
+use crate::plan::{find_conflicts, Conflict, Occupancy};
+use crate::{Rename, RenamerError};
+
+/// What to do with renames onto existing files.
+#[cfg_attr(feature = "cli", derive(clap::ArgEnum))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Leave the file to rename where it is
+    Skip,
+    /// Replace the existing file
+    Overwrite,
+    /// Move the existing file out of the way, to `name~` or `name.orig`
+    Backup,
+    /// Rename to the first free `name (N).ext` instead
+    Number,
+    /// Ask for every conflict
+    Ask,
+}
+
+impl ConflictPolicy {
+    /// The resolution for every conflict, or `None` if it is up to the user.
+    pub fn resolution(self) -> Option<Resolution> {
+        match self {
+            ConflictPolicy::Skip => Some(Resolution::Skip),
+            ConflictPolicy::Overwrite => Some(Resolution::Overwrite),
+            ConflictPolicy::Backup => Some(Resolution::Backup),
+            ConflictPolicy::Number => Some(Resolution::Number),
+            ConflictPolicy::Ask => None,
+        }
+    }
+}
+
+/// What to do with a single rename onto an existing file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resolution {
+    Skip,
+    Overwrite,
+    Backup,
+    Number,
+}
+
+/// Resolve every conflict with the resolution chosen for it.
+///
+/// Skipped renames are dropped, numbered renames get a new path, and backups
+/// are added to the batch as renames of the existing files, so undoing the
+/// batch puts them back. Renames that overwrite are kept as they are.
+///
+/// Skipping a rename keeps its file in place, which may cause new conflicts
+/// that are resolved in turn.
+pub fn resolve_conflicts(
+    mut renames: Vec<Rename>,
+    mut choose: impl FnMut(&Conflict) -> anyhow::Result<Resolution>,
+) -> anyhow::Result<Vec<Rename>> {
+    // Renames allowed to overwrite, by their original path.
+    let mut overwrites: HashSet<PathBuf> = HashSet::new();
+
+    loop {
+        let mut skipped = HashSet::new();
+        let mut renumbered = HashMap::new();
+        let mut backups = vec![];
+        {
+            let occupancy = Occupancy::new(&renames);
+            let mut taken: HashSet<PathBuf> = renames.iter().map(|r| r.new.clone()).collect();
+            let is_free = |taken: &HashSet<PathBuf>, path: &Path| {
+                !taken.contains(path) && occupancy.existing(path).is_none()
+            };
+
+            let conflicts: Vec<_> = find_conflicts(&renames)
+                .into_iter()
+                .filter(|c| !overwrites.contains(&c.rename.original))
+                .collect();
+            for conflict in &conflicts {
+                let original = conflict.rename.original.clone();
+                match choose(conflict)? {
+                    Resolution::Skip => {
+                        skipped.insert(original);
+                    }
+                    Resolution::Overwrite => {
+                        overwrites.insert(original);
+                    }
+                    Resolution::Backup => {
+                        let backup = backup_names(&conflict.rename.new)
+                            .find(|p| is_free(&taken, p))
+                            .expect("there are endless backup names");
+                        taken.insert(backup.clone());
+                        backups.push(Rename {
+                            original: conflict.existing.clone(),
+                            new: backup,
+                        });
+                    }
+                    Resolution::Number => {
+                        let new = numbered_names(&conflict.rename.new)
+                            .find(|p| is_free(&taken, p))
+                            .expect("there are endless numbered names");
+                        taken.insert(new.clone());
+                        renumbered.insert(original, new);
+                    }
+                }
+            }
+        }
+
+        if skipped.is_empty() && renumbered.is_empty() && backups.is_empty() {
+            return Ok(renames);
+        }
+        renames = backups
+            .into_iter()
+            .chain(
+                renames
+                    .into_iter()
+                    .filter(|r| !skipped.contains(&r.original))
+                    .map(|r| Rename {
+                        new: renumbered.remove(&r.original).unwrap_or(r.new),
+                        original: r.original,
+                    }),
+            )
+            .collect();
+    }
+}
+
+/// Resolve conflicts with `policy`, calling `ask` for every conflict if it is
+/// up to the user.
+///
+/// Renames onto files that are deleted in the same batch are no conflict.
+/// Every other conflict is reported to `events`. Fails if nothing is left to
+/// do.
+pub fn resolve_with_policy(
+    renames: Vec<Rename>,
+    deleted: &[PathBuf],
+    policy: ConflictPolicy,
+    events: &Events,
+    mut ask: impl FnMut(&Conflict) -> anyhow::Result<Resolution>,
+) -> anyhow::Result<Vec<Rename>> {
+    let resolved = resolve_conflicts(renames, |conflict| {
+        if deleted.contains(&conflict.existing) {
+            return Ok(Resolution::Overwrite);
+        }
+        events.emit(&Event::Conflict(conflict.rename))?;
+        match policy.resolution() {
+            Some(resolution) => Ok(resolution),
+            None => ask(conflict),
+        }
+    })?;
+    if resolved.is_empty() && deleted.is_empty() {
+        return Err(RenamerError::NoReplacementsFound.into());
+    }
+    Ok(resolved)
+}
+
+/// `name~`, `name.orig`, then `name.~1~`, `name.~2~` and so on.
+fn backup_names(path: &Path) -> impl Iterator<Item = PathBuf> + '_ {
+    vec!["~".to_string(), ".orig".to_string()]
+        .into_iter()
+        .chain((1..).map(|n| format!(".~{}~", n)))
+        .map(move |suffix| {
+            let mut name = path.as_os_str().to_os_string();
+            name.push(suffix);
+            PathBuf::from(name)
+        })
+}
+
+/// `name (1).ext`, `name (2).ext` and so on.
+fn numbered_names(path: &Path) -> impl Iterator<Item = PathBuf> + '_ {
+    (1..).map(move |n| {
+        let mut name = path.file_stem().unwrap_or_default().to_os_string();
+        name.push(format!(" ({})", n));
+        if let Some(ext) = path.extension() {
+            name.push(".");
+            name.push(ext);
+        }
+        path.with_file_name(name)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn rename(original: &Path, new: &Path) -> Rename {
+        Rename {
+            original: original.to_path_buf(),
+            new: new.to_path_buf(),
+        }
+    }
+
+    #[test]
+    fn backup_and_number_pick_free_names() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = |name: &str| dir.path().join(name);
+        for name in ["a", "b", "c.txt", "d.txt", "d (1).txt", "e", "e~"] {
+            fs::write(path(name), name)?;
+        }
+        let renames = vec![
+            rename(&path("a"), &path("e")),
+            rename(&path("c.txt"), &path("d.txt")),
+        ];
+
+        let resolved = resolve_conflicts(renames, |conflict| {
+            Ok(if conflict.rename.new == path("e") {
+                Resolution::Backup
+            } else {
+                Resolution::Number
+            })
+        })?;
+        let resolved: Vec<_> = resolved.into_iter().map(|r| (r.original, r.new)).collect();
+        assert_eq!(
+            resolved,
+            [
+                (path("e"), path("e.orig")),
+                (path("a"), path("e")),
+                (path("c.txt"), path("d (2).txt")),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn skipping_resolves_new_conflicts() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = |name: &str| dir.path().join(name);
+        for name in ["a", "b", "c"] {
+            fs::write(path(name), name)?;
+        }
+        // Skipping `b -> c` keeps `b` in the way of `a -> b`.
+        let renames = vec![
+            rename(&path("a"), &path("b")),
+            rename(&path("b"), &path("c")),
+        ];
+
+        let mut asked = vec![];
+        let resolved = resolve_conflicts(renames, |conflict| {
+            asked.push(conflict.rename.clone());
+            Ok(Resolution::Skip)
+        })?;
+        assert!(resolved.is_empty());
+        assert_eq!(asked.len(), 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+//! Encoding file names as single lines of the editor buffer.
+
+use std::borrow::Cow;
+use std::ffi::{OsStr, OsString};
+
+/// How file names are written to the editor buffer, so that names containing
+/// line breaks, tabs, trailing whitespace or invalid UTF-8 survive a round trip.
+#[cfg_attr(feature = "cli", derive(clap::ArgEnum))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Escape {
+    /// C-style escapes like `\n`, `\t`, `\\` and `\xNN` for invalid bytes
+    C,
+    /// Quote names like a POSIX shell would
+    Shell,
+}
+
+impl Escape {
+    /// Encode a file name as a single buffer line.
+    pub fn encode(self, name: &OsStr) -> Result<String, String> {
+        match (self, name.to_str()) {
+            (Escape::C, _) => Ok(c_encode(&os_str_bytes(name))),
+            (Escape::Shell, None) => Err(format!(
+                "{:?} is not valid UTF-8, use `--escape c` instead",
+                name
+            )),
+            (Escape::Shell, Some(name)) if name.contains(['\n', '\r']) => Err(format!(
+                "{:?} contains a line break, use `--escape c` instead",
+                name
+            )),
+            (Escape::Shell, Some(name)) => Ok(shell_words::quote(name).into_owned()),
+        }
+    }
+
+    /// Decode a buffer line back into a file name.
+    pub fn decode(self, line: &str) -> Result<OsString, String> {
+        match self {
+            Escape::C => Ok(os_string_from_bytes(c_decode(line)?)),
+            Escape::Shell => {
+                let mut words = shell_words::split(line).map_err(|e| e.to_string())?;
+                if words.len() != 1 {
+                    return Err(format!(
+                        "expected one quoted file name, found {}",
+                        words.len()
+                    ));
+                }
+                Ok(words.remove(0).into())
+            }
+        }
+    }
+}
+
+/// The raw bytes of a file name. Outside of Unix, names are converted to
+/// UTF-8 first.
+pub fn os_str_bytes(name: &OsStr) -> Cow<'_, [u8]> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Cow::Borrowed(name.as_bytes())
+    }
+    #[cfg(not(unix))]
+    {
+        match name.to_string_lossy() {
+            Cow::Borrowed(s) => Cow::Borrowed(s.as_bytes()),
+            Cow::Owned(s) => Cow::Owned(s.into_bytes()),
+        }
+    }
+}
+
+/// The file name made of the given raw bytes, the inverse of [`os_str_bytes`].
+pub fn os_string_from_bytes(bytes: Vec<u8>) -> OsString {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+        OsString::from_vec(bytes)
+    }
+    #[cfg(not(unix))]
+    {
+        String::from_utf8_lossy(&bytes).into_owned().into()
+    }
+}
+
+fn c_encode(name: &[u8]) -> String {
+    // Editors tend to strip trailing whitespace, so spaces at the end are escaped.
+    let trailing = name.iter().rev().take_while(|&&b| b == b' ').count();
+    let (name, spaces) = name.split_at(name.len() - trailing);
+
+    let mut encoded = String::with_capacity(name.len());
+    for chunk in name.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => encoded.push_str("\\\\"),
+                '\n' => encoded.push_str("\\n"),
+                '\t' => encoded.push_str("\\t"),
+                '\r' => encoded.push_str("\\r"),
+                c if c.is_control() => {
+                    for b in c.encode_utf8(&mut [0; 4]).bytes() {
+                        encoded.push_str(&format!("\\x{:02x}", b));
+                    }
+                }
+                c => encoded.push(c),
+            }
+        }
+        for b in chunk.invalid() {
+            encoded.push_str(&format!("\\x{:02x}", b));
+        }
+    }
+    encoded.push_str(&"\\x20".repeat(spaces.len()));
+    encoded
+}
+
+fn c_decode(line: &str) -> Result<Vec<u8>, String> {
+    let mut decoded = Vec::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            decoded.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => decoded.push(b'\\'),
+            Some('n') => decoded.push(b'\n'),
+            Some('t') => decoded.push(b'\t'),
+            Some('r') => decoded.push(b'\r'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let byte = u8::from_str_radix(&hex, 16)
+                    .ok()
+                    .filter(|_| hex.len() == 2)
+                    .ok_or_else(|| format!("invalid escape sequence \\x{}", hex))?;
+                decoded.push(byte);
+            }
+            Some(c) => return Err(format!("unknown escape sequence \\{}", c)),
+            None => return Err("trailing backslash".to_string()),
+        }
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn c_round_trip() {
+        for name in [
+            "plain",
+            "new\nline",
+            "tab\there",
+            "back\\slash",
+            "trailing  ",
+            "\u{1b}[0m",
+        ] {
+            let encoded = Escape::C.encode(name.as_ref()).unwrap();
+            assert!(!encoded.contains('\n') && !encoded.ends_with(' '));
+            assert_eq!(Escape::C.decode(&encoded).unwrap(), name);
+        }
+        assert_eq!(
+            Escape::C.encode("a\nb \\ ".as_ref()).unwrap(),
+            "a\\nb \\\\\\x20"
+        );
+        assert!(Escape::C.decode("a\\q").is_err());
+    }
+
+    #[test]
+    fn shell_round_trip() {
+        for name in ["plain", "with space", "it's", "trailing "] {
+            let encoded = Escape::Shell.encode(name.as_ref()).unwrap();
+            assert_eq!(Escape::Shell.decode(&encoded).unwrap(), name);
+        }
+        assert!(Escape::Shell.encode("new\nline".as_ref()).is_err());
+        assert!(Escape::Shell.decode("two words").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn invalid_utf8_round_trip() {
+        let name = os_string_from_bytes(b"caf\xe9 \\x".to_vec());
+        let encoded = Escape::C.encode(&name).unwrap();
+        assert_eq!(encoded, "caf\\xe9 \\\\x");
+        assert_eq!(Escape::C.decode(&encoded).unwrap(), name);
+        assert!(Escape::Shell.encode(&name).is_err());
+    }
+}
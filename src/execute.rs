@@ -0,0 +1,355 @@
+//! Executing renames on the file system.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+
+use crate::backend::RenameBackend;
+use crate::git::IndexMoves;
+use crate::output::{Event, Events};
+use crate::plan::plan_renames;
+use crate::trash;
+use crate::Rename;
+
+/// A change to the file system that has been applied and can be reverted.
+enum Entry {
+    Renamed(Rename),
+    CreatedDir(PathBuf),
+}
+
+/// Record of the steps applied so far, so a failed batch can be rolled back.
+#[derive(Default)]
+struct Journal {
+    entries: Vec<Entry>,
+}
+
+impl Journal {
+    /// Revert all recorded steps in reverse order, returning the renames that
+    /// could not be reverted.
+    fn rollback(self, backend: &mut dyn RenameBackend) -> Vec<(Rename, anyhow::Error)> {
+        let mut failures = vec![];
+        for entry in self.entries.into_iter().rev() {
+            match entry {
+                Entry::Renamed(step) => {
+                    if let Err(e) = backend.rename(&step.new, &step.original) {
+                        failures.push((step, e));
+                    }
+                }
+                // Only empty directories are removed, anything else is left alone.
+                Entry::CreatedDir(dir) => {
+                    let _ = backend.remove_dir(&dir);
+                }
+            }
+        }
+        failures
+    }
+}
+
+/// Execute all renames, or none of them.
+///
+/// If any step fails, the steps that were already applied are rolled back in
+/// reverse order before the error is returned.
+///
+/// A rename is reported as done once the step moving it to its new path has
+/// been executed, moves to temporary names are not reported.
+pub fn execute_renames(
+    replacements: &[Rename],
+    backend: &mut dyn RenameBackend,
+    parents: bool,
+    events: &Events,
+) -> anyhow::Result<()> {
+    let steps = plan_renames(replacements);
+    let mut journal = Journal::default();
+    let by_target: HashMap<&Path, &Rename> =
+        replacements.iter().map(|r| (r.new.as_path(), r)).collect();
+
+    for (i, step) in steps.iter().enumerate() {
+        let rename = by_target.get(step.new.as_path()).copied();
+        if let Err(e) = execute_step(step, backend, parents, &mut journal) {
+            events.emit(&Event::RenameFailed {
+                rename: rename.unwrap_or(step),
+                error: format!("{:#}", e),
+            })?;
+            let failed = format!(
+                "Could not rename {} (step {} of {})",
+                step,
+                i + 1,
+                steps.len()
+            );
+            let completed = journal
+                .entries
+                .iter()
+                .filter(|entry| matches!(entry, Entry::Renamed(_)))
+                .count();
+            let failures = journal.rollback(backend);
+            if failures.is_empty() {
+                return Err(e.context(format!(
+                    "{}, rolled back {} completed renames",
+                    failed, completed
+                )));
+            }
+
+            let mut message = format!(
+                "{}, rollback failed for {} of {} completed renames:",
+                failed,
+                failures.len(),
+                completed
+            );
+            for (step, error) in failures {
+                message.push_str(&format!("\n    {}: {}", step, error));
+            }
+            return Err(e.context(message));
+        }
+        if let Some(rename) = rename {
+            events.emit(&Event::RenameDone(rename))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Files moved between their place and the trash around a batch of renames.
+#[derive(Debug, Clone, Copy)]
+pub enum TrashMoves<'a> {
+    /// Move the files into the trash before renaming.
+    Stash(&'a [Rename]),
+    /// Move the files back after renaming, when undoing.
+    Restore(&'a [Rename]),
+}
+
+/// A batch of renames along with what keeps it undoable.
+#[derive(Debug, Clone, Copy)]
+pub struct Batch<'a> {
+    pub renames: &'a [Rename],
+    /// Create missing parent directories of the new paths.
+    pub parents: bool,
+    /// The git index entries to move along with the files.
+    pub index: Option<&'a IndexMoves>,
+    /// The overwritten and deleted files, see [`trash::trash_moves`].
+    pub trash: TrashMoves<'a>,
+}
+
+/// Execute a batch of renames with everything around it, all or nothing.
+///
+/// Files are moved into the trash before renaming, or out of it afterwards
+/// when undoing. Once the files are renamed, the git index entries are moved,
+/// and the renames are rolled back if that fails. Stashed files are put back
+/// if anything fails. The outcome is reported to `events` as a summary.
+///
+/// Problems that do not stop the batch, like originals left behind after
+/// copying them, are passed to `warn`, also when the batch fails.
+pub fn run(
+    batch: &Batch,
+    backend: &mut dyn RenameBackend,
+    events: &Events,
+    warn: &mut dyn FnMut(&str),
+) -> anyhow::Result<()> {
+    let mut warnings = vec![];
+    let result = run_batch(batch, backend, events, &mut warnings);
+    warnings.extend(backend.take_warnings());
+    for warning in &warnings {
+        warn(warning);
+    }
+    events.emit(&Event::Summary {
+        renamed: if result.is_ok() {
+            batch.renames.len()
+        } else {
+            0
+        },
+        error: result.as_ref().err().map(|e| format!("{:#}", e)),
+    })?;
+    result
+}
+
+fn run_batch(
+    batch: &Batch,
+    backend: &mut dyn RenameBackend,
+    events: &Events,
+    warnings: &mut Vec<String>,
+) -> anyhow::Result<()> {
+    if let TrashMoves::Stash(moves) = batch.trash {
+        warnings.extend(trash::stash(moves).context("Could not move files to the trash")?);
+    }
+
+    let mut result = execute_renames(batch.renames, backend, batch.parents, events);
+    if let (Ok(()), Some(index)) = (&result, batch.index) {
+        if let Err(e) = index.apply() {
+            // Put the files back where the index expects them.
+            let undo: Vec<Rename> = batch
+                .renames
+                .iter()
+                .map(|r| Rename {
+                    original: r.new.clone(),
+                    new: r.original.clone(),
+                })
+                .collect();
+            let rolled_back = execute_renames(&undo, backend, false, &Events::default());
+            result = Err(match rolled_back {
+                Ok(()) => e.context("Could not update the git index, rolled back the renames"),
+                Err(rollback) => e.context(format!(
+                    "Could not update the git index, and rolling back the renames failed: {:#}",
+                    rollback
+                )),
+            });
+        }
+    }
+
+    match (batch.trash, result) {
+        (TrashMoves::Stash(moves), Err(e)) => match trash::restore(moves) {
+            Ok(restored) => {
+                warnings.extend(restored);
+                Err(e)
+            }
+            Err(restore) => Err(anyhow!(
+                "{:#}, and restoring files from the trash failed: {:#}",
+                e,
+                restore
+            )),
+        },
+        (TrashMoves::Restore(moves), Ok(())) => {
+            warnings
+                .extend(trash::restore(moves).context("Could not restore files from the trash")?);
+            Ok(())
+        }
+        (_, result) => result,
+    }
+}
+
+fn execute_step(
+    step: &Rename,
+    backend: &mut dyn RenameBackend,
+    parents: bool,
+    journal: &mut Journal,
+) -> anyhow::Result<()> {
+    if parents && backend.creates_dirs() {
+        if let Some(dir) = step.new.parent() {
+            create_missing_dirs(dir, backend, journal)?;
+        }
+    }
+
+    backend.rename(&step.original, &step.new)?;
+    journal.entries.push(Entry::Renamed(step.clone()));
+    Ok(())
+}
+
+/// Create `dir` and its missing ancestors from the outermost to the innermost,
+/// journaling each directory as soon as it is created.
+fn create_missing_dirs(
+    dir: &Path,
+    backend: &mut dyn RenameBackend,
+    journal: &mut Journal,
+) -> io::Result<()> {
+    let mut missing: Vec<_> = dir
+        .ancestors()
+        .take_while(|d| !d.as_os_str().is_empty() && d.symlink_metadata().is_err())
+        .map(PathBuf::from)
+        .collect();
+    missing.reverse();
+
+    for d in missing {
+        backend.create_dir(&d)?;
+        journal.entries.push(Entry::CreatedDir(d));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    use crate::backend::FsBackend;
+
+    /// Fails to create the second directory.
+    #[derive(Default)]
+    struct FailingDirs {
+        inner: FsBackend,
+        created: usize,
+    }
+
+    impl RenameBackend for FailingDirs {
+        fn rename(&mut self, from: &Path, to: &Path) -> anyhow::Result<()> {
+            self.inner.rename(from, to)
+        }
+
+        fn create_dir(&mut self, dir: &Path) -> io::Result<()> {
+            self.created += 1;
+            if self.created == 2 {
+                return Err(io::Error::from(io::ErrorKind::PermissionDenied));
+            }
+            fs::create_dir(dir)
+        }
+    }
+
+    #[test]
+    fn rollback_removes_partly_created_dirs() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        fs::write(&a, "")?;
+        fs::write(&b, "")?;
+        let renames = [
+            Rename {
+                original: a.clone(),
+                new: dir.path().join("x"),
+            },
+            Rename {
+                original: b.clone(),
+                new: dir.path().join("d/e/b"),
+            },
+        ];
+
+        let result = execute_renames(
+            &renames,
+            &mut FailingDirs::default(),
+            true,
+            &Events::default(),
+        );
+        assert!(result.is_err());
+        assert!(a.exists() && b.exists());
+        assert!(!dir.path().join("d").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn failed_batch_restores_the_trash() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (a, b, x) = (
+            dir.path().join("a"),
+            dir.path().join("b"),
+            dir.path().join("x"),
+        );
+        fs::write(&a, "a")?;
+        fs::write(&b, "b")?;
+        fs::write(&x, "x")?;
+        let renames = [
+            Rename {
+                original: a.clone(),
+                new: x.clone(),
+            },
+            Rename {
+                original: b.clone(),
+                new: dir.path().join("d/e/b"),
+            },
+        ];
+        let stashed = trash::stash_moves(&renames, &[], &dir.path().join("trash"));
+        let batch = Batch {
+            renames: &renames,
+            parents: true,
+            index: None,
+            trash: TrashMoves::Stash(&stashed),
+        };
+
+        let result = run(
+            &batch,
+            &mut FailingDirs::default(),
+            &Events::default(),
+            &mut |_| {},
+        );
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&a)?, "a");
+        assert_eq!(fs::read_to_string(&x)?, "x");
+        Ok(())
+    }
+}
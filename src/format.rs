@@ -0,0 +1,134 @@
+//! Machine-readable formats for rename plans.
+
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{BufReader, Write};
+use std::path::Path;
+
+use anyhow::{bail, Context};
+
+use crate::escape::Escape;
+use crate::plan::plan_renames;
+use crate::Rename;
+
+/// Machine-readable formats for printing a plan with `--dry-run`.
+#[cfg_attr(feature = "cli", derive(clap::ArgEnum))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlanFormat {
+    /// A JSON array of objects with `original` and `new` paths
+    Json,
+    /// One rename per line, with the paths separated by a tab and C-style escapes
+    Tsv,
+    /// A shell script executing the renames in a safe order
+    Shell,
+}
+
+/// Write the renames in the given format.
+pub fn write_plan(
+    renames: &[Rename],
+    format: PlanFormat,
+    rename_command: Option<&str>,
+    parents: bool,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    match format {
+        PlanFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, renames)?;
+            writeln!(out)?;
+        }
+        PlanFormat::Tsv => {
+            for rename in renames {
+                let original = Escape::C.encode(rename.original.as_os_str());
+                let new = Escape::C.encode(rename.new.as_os_str());
+                writeln!(
+                    out,
+                    "{}\t{}",
+                    original.map_err(anyhow::Error::msg)?,
+                    new.map_err(anyhow::Error::msg)?
+                )?;
+            }
+        }
+        PlanFormat::Shell => write_script(renames, rename_command, parents, out)?,
+    }
+    Ok(())
+}
+
+/// Read a plan written in the JSON format.
+pub fn read_plan(path: &Path) -> anyhow::Result<Vec<Rename>> {
+    let file = File::open(path).with_context(|| format!("Could not open {}", path.display()))?;
+    serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("Could not parse {}", path.display()))
+}
+
+fn write_script(
+    renames: &[Rename],
+    rename_command: Option<&str>,
+    parents: bool,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    let command = match rename_command {
+        Some(cmd) => shell_words::join(shell_words::split(cmd)?),
+        None => "mv --".to_string(),
+    };
+
+    writeln!(out, "#!/bin/sh")?;
+    writeln!(out, "set -e")?;
+    // Chains and cycles only work in this order, and may go through
+    // temporary names.
+    for step in plan_renames(renames) {
+        if parents && rename_command.is_none() {
+            if let Some(dir) = step.new.parent().filter(|d| !d.as_os_str().is_empty()) {
+                writeln!(out, "mkdir -p -- {}", quote(dir.as_os_str())?)?;
+            }
+        }
+        writeln!(
+            out,
+            "{} {} {}",
+            command,
+            quote(step.original.as_os_str())?,
+            quote(step.new.as_os_str())?
+        )?;
+    }
+    Ok(())
+}
+
+fn quote(name: &OsStr) -> anyhow::Result<String> {
+    match name.to_str() {
+        Some(name) => Ok(shell_words::quote(name).into_owned()),
+        None => bail!(
+            "{} is not valid UTF-8 and cannot be written to a shell script",
+            Path::new(name).display()
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rename(original: &str, new: &str) -> Rename {
+        Rename {
+            original: original.into(),
+            new: new.into(),
+        }
+    }
+
+    fn plan(renames: &[Rename], format: PlanFormat) -> String {
+        let mut out = vec![];
+        write_plan(renames, format, None, false, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn formats() {
+        let renames = [rename("it's", "b\tc"), rename("b\tc", "d")];
+        assert_eq!(plan(&renames, PlanFormat::Tsv), "it's\tb\\tc\nb\\tc\td\n");
+        assert_eq!(
+            plan(&renames, PlanFormat::Shell),
+            "#!/bin/sh\nset -e\nmv -- 'b\tc' d\nmv -- 'it'\\''s' 'b\tc'\n"
+        );
+
+        let json: Vec<Rename> = serde_json::from_str(&plan(&renames, PlanFormat::Json)).unwrap();
+        assert_eq!(json[0].new, renames[0].new);
+    }
+}
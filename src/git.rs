@@ -0,0 +1,293 @@
+//! Keeping the git index in sync with renamed files.
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::escape::{os_str_bytes, os_string_from_bytes};
+use crate::history::make_absolute;
+use crate::path_serde;
+use crate::Rename;
+
+/// Index entries of tracked files to move along with the renamed files, like
+/// `git mv` would, with paths relative to the repository root.
+///
+/// Untracked files are not part of the index and are left alone.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexMoves {
+    #[serde(with = "path_serde")]
+    pub root: PathBuf,
+    pub moves: Vec<Rename>,
+    /// The entries of tracked files overwritten by the moves, which are
+    /// removed along with them.
+    #[serde(default)]
+    pub replaced: Vec<IndexEntry>,
+    /// The entries to add back after moving, when undoing.
+    #[serde(default)]
+    pub restored: Vec<IndexEntry>,
+}
+
+impl IndexMoves {
+    /// Find the tracked files affected by the renames, in the repository
+    /// containing each renamed file.
+    ///
+    /// Returns `None` outside of a repository or if no tracked files are
+    /// renamed. Fails if tracked files of several repositories are renamed, or
+    /// files with merge conflicts.
+    pub fn find(renames: &[Rename]) -> anyhow::Result<Option<IndexMoves>> {
+        // The repositories of the renamed files, by the directory they are in.
+        let mut roots: HashMap<PathBuf, Option<PathBuf>> = HashMap::new();
+        let mut relative: Vec<(PathBuf, Rename)> = vec![];
+        for rename in renames {
+            let original = make_absolute(&rename.original)?;
+            let dir = existing_ancestor(&original);
+            if !roots.contains_key(dir) {
+                roots.insert(dir.to_path_buf(), repository_root(dir)?);
+            }
+            let root = match &roots[dir] {
+                Some(root) => root,
+                None => continue,
+            };
+            // Files moved into or out of the repository are not tracked by git
+            // mv either.
+            if let (Some(original), Some(new)) = (
+                repo_relative(root, &rename.original)?,
+                repo_relative(root, &rename.new)?,
+            ) {
+                relative.push((root.clone(), Rename { original, new }));
+            }
+        }
+
+        let mut found: Option<IndexMoves> = None;
+        for (root, _) in &relative {
+            if found.as_ref().is_some_and(|f| f.root == *root) {
+                continue;
+            }
+            let renames: Vec<&Rename> = relative
+                .iter()
+                .filter(|(r, _)| r == root)
+                .map(|(_, rename)| rename)
+                .collect();
+            let moves = index_moves(root, &renames)?;
+            if moves.is_empty() {
+                continue;
+            }
+            if let Some(found) = &found {
+                bail!(
+                    "Cannot move index entries in both {} and {}.",
+                    found.root.display(),
+                    root.display()
+                );
+            }
+            // Entries that are moved away themselves are not overwritten.
+            let news: Vec<&Path> = renames.iter().map(|r| r.new.as_path()).collect();
+            let replaced = list_index(root, &news)?
+                .into_iter()
+                .filter(|entry| !moves.iter().any(|m| m.original == entry.path))
+                .collect();
+            found = Some(IndexMoves {
+                root: root.clone(),
+                moves,
+                replaced,
+                restored: vec![],
+            });
+        }
+        Ok(found)
+    }
+
+    /// The moves that revert these ones, bringing back the overwritten
+    /// entries.
+    pub fn reversed(&self) -> IndexMoves {
+        IndexMoves {
+            root: self.root.clone(),
+            moves: self
+                .moves
+                .iter()
+                .map(|m| Rename {
+                    original: m.new.clone(),
+                    new: m.original.clone(),
+                })
+                .collect(),
+            replaced: vec![],
+            restored: self.replaced.clone(),
+        }
+    }
+
+    /// Move the index entries in one batch, keeping their staged content.
+    pub fn apply(&self) -> anyhow::Result<()> {
+        let originals: Vec<&Path> = self.moves.iter().map(|m| m.original.as_path()).collect();
+        let entries = list_index(&self.root, &originals)?;
+
+        // All entries are removed before any are added, so swapping works.
+        let mut info = vec![];
+        for entry in entries.iter().chain(&self.replaced) {
+            info.extend_from_slice(b"0 0000000000000000000000000000000000000000\t");
+            info.extend_from_slice(&os_str_bytes(entry.path.as_os_str()));
+            info.push(b'\0');
+        }
+        for entry in &entries {
+            let new = match self.moves.iter().find(|m| m.original == entry.path) {
+                Some(m) => &m.new,
+                None => continue,
+            };
+            info.extend_from_slice(format!("{} {}\t", entry.mode, entry.object).as_bytes());
+            info.extend_from_slice(&os_str_bytes(new.as_os_str()));
+            info.push(b'\0');
+        }
+        for entry in &self.restored {
+            info.extend_from_slice(format!("{} {}\t", entry.mode, entry.object).as_bytes());
+            info.extend_from_slice(&os_str_bytes(entry.path.as_os_str()));
+            info.push(b'\0');
+        }
+
+        let mut child = git(&self.root)
+            .args(["update-index", "-z", "--index-info"])
+            .stdin(Stdio::piped())
+            .spawn()
+            .context("Could not run git")?;
+        child
+            .stdin
+            .take()
+            .expect("stdin is piped")
+            .write_all(&info)?;
+        let status = child.wait()?;
+        if !status.success() {
+            bail!("git update-index exited with {}", status);
+        }
+        Ok(())
+    }
+}
+
+/// The moves of the index entries at or below the renamed paths, which are
+/// relative to `root`.
+fn index_moves(root: &Path, renames: &[&Rename]) -> anyhow::Result<Vec<Rename>> {
+    let originals: Vec<&Path> = renames.iter().map(|r| r.original.as_path()).collect();
+    let mut moves = vec![];
+    for entry in list_index(root, &originals)? {
+        // The deepest rename decides where nested entries end up.
+        let rename = renames
+            .iter()
+            .filter(|r| entry.path.starts_with(&r.original))
+            .max_by_key(|r| r.original.components().count())
+            .expect("listed entries match a rename");
+        let rel = entry.path.strip_prefix(&rename.original)?;
+        let new = if rel.as_os_str().is_empty() {
+            rename.new.clone()
+        } else {
+            rename.new.join(rel)
+        };
+        moves.push(Rename {
+            original: entry.path,
+            new,
+        });
+    }
+    Ok(moves)
+}
+
+/// An entry of the index, with its path relative to the repository root.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexEntry {
+    pub mode: String,
+    pub object: String,
+    #[serde(with = "path_serde")]
+    pub path: PathBuf,
+}
+
+fn git(root: &Path) -> Command {
+    let mut command = Command::new("git");
+    command.current_dir(root);
+    command
+}
+
+/// The root of the repository containing `dir`.
+fn repository_root(dir: &Path) -> anyhow::Result<Option<PathBuf>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["rev-parse", "--show-toplevel"])
+        .stderr(Stdio::null())
+        .output()
+        .context("Could not run git")?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+    let mut root = output.stdout;
+    if root.last() == Some(&b'\n') {
+        root.pop();
+    }
+    Ok(Some(fs::canonicalize(os_string_from_bytes(root))?))
+}
+
+/// The path relative to the repository root, resolving symbolic links in the
+/// directories that exist.
+fn repo_relative(root: &Path, path: &Path) -> anyhow::Result<Option<PathBuf>> {
+    let path = make_absolute(path)?;
+    let existing = existing_ancestor(&path);
+    let rest = path.strip_prefix(existing)?;
+    let resolved = fs::canonicalize(existing)?.join(rest);
+    Ok(resolved.strip_prefix(root).ok().map(Path::to_path_buf))
+}
+
+/// The closest directory above `path` that exists.
+fn existing_ancestor(path: &Path) -> &Path {
+    path.ancestors()
+        .skip(1)
+        .find(|a| a.exists())
+        .expect("the root directory exists")
+}
+
+/// List the index entries at or below the given paths, which must not have
+/// merge conflicts.
+fn list_index(root: &Path, paths: &[&Path]) -> anyhow::Result<Vec<IndexEntry>> {
+    let pathspecs = paths.iter().map(|p| {
+        let mut spec = OsStr::new(":(literal)").to_os_string();
+        spec.push(p.as_os_str());
+        spec
+    });
+    let output = git(root)
+        .args(["ls-files", "--stage", "-z", "--"])
+        .args(pathspecs)
+        .output()
+        .context("Could not run git")?;
+    if !output.status.success() {
+        bail!(
+            "git ls-files failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    let mut entries = vec![];
+    for record in output
+        .stdout
+        .split(|&b| b == b'\0')
+        .filter(|r| !r.is_empty())
+    {
+        // <mode> SP <object> SP <stage> TAB <path>
+        let tab = record
+            .iter()
+            .position(|&b| b == b'\t')
+            .context("Unexpected output from git ls-files")?;
+        let info = String::from_utf8_lossy(&record[..tab]);
+        let fields: Vec<&str> = info.split(' ').collect();
+        let path = PathBuf::from(os_string_from_bytes(record[tab + 1..].to_vec()));
+        match fields[..] {
+            [mode, object, "0"] => entries.push(IndexEntry {
+                mode: mode.to_string(),
+                object: object.to_string(),
+                path,
+            }),
+            [_, _, _] => bail!(
+                "{} has merge conflicts, resolve them before renaming it.",
+                path.display()
+            ),
+            _ => bail!("Unexpected output from git ls-files"),
+        }
+    }
+    Ok(entries)
+}
@@ -0,0 +1,323 @@
+//! The undo/redo history of renaming operations.
+
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::git::IndexMoves;
+use crate::trash;
+use crate::Rename;
+
+/// Number of operations kept in the history, older ones are dropped.
+const MAX_OPERATIONS: usize = 100;
+
+/// A renaming operation as it was executed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Operation {
+    pub id: u64,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    #[serde(with = "crate::path_serde")]
+    pub cwd: PathBuf,
+    pub command: String,
+    /// The renames with absolute paths, in the direction they were executed.
+    pub renames: Vec<Rename>,
+    #[serde(default)]
+    pub undone: bool,
+    /// The git index entries moved along with the files.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git: Option<IndexMoves>,
+    /// The overwritten and deleted files, moved to the trash before renaming.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trash: Vec<Rename>,
+}
+
+impl Operation {
+    /// The renames that revert this operation.
+    pub fn undo_renames(&self) -> Vec<Rename> {
+        self.renames
+            .iter()
+            .map(|r| Rename {
+                original: r.new.clone(),
+                new: r.original.clone(),
+            })
+            .collect()
+    }
+
+    pub fn time(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.timestamp)
+    }
+
+    /// Whether this operation was run in or touched any file below `dir`.
+    pub fn touches(&self, dir: &Path) -> bool {
+        self.cwd.starts_with(dir)
+            || self
+                .renames
+                .iter()
+                .any(|r| r.original.starts_with(dir) || r.new.starts_with(dir))
+            || self.trash.iter().any(|t| t.original.starts_with(dir))
+    }
+
+    fn in_scope(&self, scope: Option<&Path>) -> bool {
+        scope.is_none_or(|dir| self.touches(dir))
+    }
+}
+
+/// Undo/redo history, persisted as JSON in the state directory.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct History {
+    #[serde(skip)]
+    path: PathBuf,
+    operations: Vec<Operation>,
+    /// Ids of undone operations, the most recently undone last.
+    #[serde(default)]
+    redo: Vec<u64>,
+}
+
+impl History {
+    /// Load the history from `dir`, starting a new one if there is none yet.
+    pub fn load(dir: &Path) -> anyhow::Result<Self> {
+        let path = dir.join("history.json");
+        let mut history: History = match fs::File::open(&path) {
+            Ok(file) => serde_json::from_reader(io::BufReader::new(file))
+                .with_context(|| format!("Could not parse {}", path.display()))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => History::default(),
+            Err(e) => return Err(e.into()),
+        };
+        history.path = path;
+        Ok(history)
+    }
+
+    /// Write the history back to the state directory.
+    pub fn save(&self) -> anyhow::Result<()> {
+        let dir = self.path.parent().expect("history file has a parent");
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Could not create state directory {}", dir.display()))?;
+
+        // Write to a temporary file first so a crash cannot corrupt the history.
+        let mut file = tempfile::NamedTempFile::new_in(dir)?;
+        serde_json::to_writer(&mut file, self)?;
+        file.flush()?;
+        file.persist(&self.path)?;
+        Ok(())
+    }
+
+    /// The recorded operations, limited to those touching `scope` if given.
+    pub fn operations<'a>(
+        &'a self,
+        scope: Option<&'a Path>,
+    ) -> impl Iterator<Item = &'a Operation> + 'a {
+        self.operations.iter().filter(move |op| op.in_scope(scope))
+    }
+
+    /// Add a newly executed operation, which makes undone operations
+    /// unavailable for redo.
+    pub fn record(
+        &mut self,
+        renames: &[Rename],
+        command: String,
+        git: Option<IndexMoves>,
+        trash: Vec<Rename>,
+    ) -> anyhow::Result<u64> {
+        // Make paths absolute so that undo does not depend on CWD.
+        let absolute = |renames: &[Rename]| {
+            renames
+                .iter()
+                .map(|r| {
+                    Ok(Rename {
+                        original: make_absolute(&r.original)?,
+                        new: make_absolute(&r.new)?,
+                    })
+                })
+                .collect::<anyhow::Result<Vec<_>>>()
+        };
+        let renames = absolute(renames)?;
+        let trash = absolute(&trash)?;
+
+        let id = self.operations.last().map_or(1, |op| op.id + 1);
+        self.operations.push(Operation {
+            id,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            cwd: env::current_dir()?,
+            command,
+            renames,
+            undone: false,
+            git,
+            trash,
+        });
+        self.redo.clear();
+
+        if self.operations.len() > MAX_OPERATIONS {
+            let excess = self.operations.len() - MAX_OPERATIONS;
+            for op in self.operations.drain(..excess) {
+                if !op.undone {
+                    trash::purge(&op.trash);
+                }
+            }
+        }
+        Ok(id)
+    }
+
+    /// Find the operation to undo, either by id or the most recent one that
+    /// has not been undone yet.
+    ///
+    /// Only operations touching `scope` are considered, if given.
+    pub fn undo_candidate(
+        &self,
+        id: Option<u64>,
+        scope: Option<&Path>,
+    ) -> anyhow::Result<&Operation> {
+        match id {
+            Some(id) => match self.operations.iter().find(|op| op.id == id) {
+                Some(op) if op.undone => bail!("Operation {} has already been undone.", id),
+                Some(op) if !op.in_scope(scope) => bail!(
+                    "Operation {} did not touch the current directory. \
+                     Use --any-dir to undo it anyway.",
+                    id
+                ),
+                Some(op) => Ok(op),
+                None => bail!("Operation {} not found in history.", id),
+            },
+            None => match self.operations.iter().rev().find(|op| !op.undone) {
+                None => bail!("No undo information found."),
+                Some(_) => self
+                    .operations
+                    .iter()
+                    .rev()
+                    .find(|op| !op.undone && op.in_scope(scope))
+                    .context(
+                        "No undo information found for the current directory. \
+                         Use --any-dir to undo operations from other directories.",
+                    ),
+            },
+        }
+    }
+
+    /// Find the most recently undone operation, among those touching `scope`
+    /// if given.
+    pub fn redo_candidate(&self, scope: Option<&Path>) -> anyhow::Result<&Operation> {
+        self.redo
+            .iter()
+            .rev()
+            .filter_map(|id| self.operations.iter().find(|op| op.id == *id))
+            .find(|op| op.in_scope(scope))
+            .context("No redo information found.")
+    }
+
+    pub fn mark_undone(&mut self, id: u64) {
+        if let Some(op) = self.operations.iter_mut().find(|op| op.id == id) {
+            op.undone = true;
+            self.redo.retain(|&i| i != id);
+            self.redo.push(id);
+        }
+    }
+
+    pub fn mark_redone(&mut self, id: u64) {
+        if let Some(op) = self.operations.iter_mut().find(|op| op.id == id) {
+            op.undone = false;
+            self.redo.retain(|&i| i != id);
+        }
+    }
+}
+
+/// Directory for persistent state, following the XDG base directory spec.
+pub fn state_dir() -> anyhow::Result<PathBuf> {
+    let xdg_state = env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute());
+    let base = match xdg_state {
+        Some(dir) => dir,
+        None if cfg!(windows) => env::var_os("LOCALAPPDATA")
+            .map(PathBuf::from)
+            .context("Could not determine state directory, LOCALAPPDATA is not set.")?,
+        None => env::var_os("HOME")
+            .map(|home| PathBuf::from(home).join(".local").join("state"))
+            .context("Could not determine state directory, HOME is not set.")?,
+    };
+    Ok(base.join("pipe-rename"))
+}
+
+/// Record an executed operation in the history in the state directory,
+/// returning its id.
+pub fn record_operation(
+    renames: &[Rename],
+    command: String,
+    git: Option<IndexMoves>,
+    trash: Vec<Rename>,
+) -> anyhow::Result<u64> {
+    let mut history = History::load(&state_dir()?)?;
+    let id = history.record(renames, command, git, trash)?;
+    history.save()?;
+    Ok(id)
+}
+
+/// Check that every file to be renamed by an undo or redo is still in place.
+pub fn check_restorable(renames: &[Rename], action: &str) -> anyhow::Result<()> {
+    for rename in renames {
+        if rename.original.symlink_metadata().is_err() {
+            bail!(
+                "{} not possible. \"{}\" is missing.",
+                action,
+                rename.original.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Make `path` absolute and resolve `.` and `..` components lexically, so
+/// that recorded paths can be compared against directories.
+pub fn make_absolute(path: &Path) -> anyhow::Result<PathBuf> {
+    let path = if path.is_relative() {
+        env::current_dir()?.join(path)
+    } else {
+        path.to_path_buf()
+    };
+
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            c => normalized.push(c),
+        }
+    }
+    Ok(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operation_round_trip() {
+        let name = crate::escape::os_string_from_bytes(b"/tmp/caf\xe9".to_vec());
+        let op = Operation {
+            id: 1,
+            timestamp: 0,
+            cwd: "/tmp".into(),
+            command: "renamer".into(),
+            renames: vec![Rename {
+                original: name.clone().into(),
+                new: "/tmp/cafe".into(),
+            }],
+            undone: false,
+            git: None,
+            trash: vec![],
+        };
+
+        let json = serde_json::to_string(&op).unwrap();
+        let parsed: Operation = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.renames[0].original, PathBuf::from(name));
+        assert_eq!(parsed.renames[0].new, PathBuf::from("/tmp/cafe"));
+    }
+}
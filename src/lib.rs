@@ -0,0 +1,267 @@
+//! The rename engine behind the `renamer` command line tool.
+//!
+//! Renaming goes through the same steps as on the command line:
+//!
+//! 1. Build the renames from the original and the edited file names with
+//!    [`find_renames`].
+//! 2. Validate them with [`plan::check_input_files`] and
+//!    [`plan::blocking_conflicts`], which finds renames that would overwrite
+//!    existing files, and resolve those with
+//!    [`conflict::resolve_with_policy`].
+//! 3. Execute them with [`execute::execute_renames`], which orders the renames
+//!    so that chains, cycles and nested directories work, and rolls back on
+//!    failure. The files are moved by a [`backend::RenameBackend`].
+//!    [`execute::run`] does the same for a whole [`execute::Batch`], moving
+//!    the overwritten files to the [`trash`] and the git index entries along
+//!    with the files, see [`git::IndexMoves`].
+//! 4. Record them with [`history::record_operation`] so that they can be
+//!    undone with [`history::Operation::undo_renames`].
+//!
+//! ```
+//! use std::fs;
+//! use std::path::PathBuf;
+//!
+//! use pipe_rename::backend::FsBackend;
+//! use pipe_rename::execute::execute_renames;
+//! use pipe_rename::find_renames;
+//! use pipe_rename::output::Events;
+//! use pipe_rename::plan::find_conflicts;
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let dir = tempfile::tempdir()?;
+//! let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+//! fs::write(&a, "")?;
+//!
+//! let renames = find_renames(&[a], &[b.clone()])?;
+//! assert!(find_conflicts(&renames).is_empty());
+//! execute_renames(&renames, &mut FsBackend::default(), false, &Events::default())?;
+//! assert!(b.exists());
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashSet;
+use std::env;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+use ansi_term::Colour;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+pub mod backend;
+pub mod conflict;
+pub mod escape;
+pub mod execute;
+pub mod format;
+pub mod git;
+pub mod history;
+pub mod output;
+pub mod plan;
+pub mod sort;
+pub mod substitute;
+mod text_diff;
+pub mod trash;
+pub mod walk;
+
+use plan::rebase_nested;
+use text_diff::{calculate_text_diff, TextDiff};
+
+/// A file to be renamed from its original path to a new one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rename {
+    #[serde(with = "path_serde")]
+    pub original: PathBuf,
+    #[serde(with = "path_serde")]
+    pub new: PathBuf,
+}
+
+impl Rename {
+    /// Create a rename, expanding a leading `~` in the new path.
+    pub fn new(original: &Path, new: &Path) -> Self {
+        // Expand ~ if applicable.
+        let mut new = new.to_path_buf();
+        if let Some(home) = env::var_os("HOME") {
+            if let Ok(rest) = new.strip_prefix("~") {
+                if !rest.as_os_str().is_empty() {
+                    new = Path::new(&home).join(rest);
+                }
+            }
+        }
+
+        Rename {
+            original: original.into(),
+            new,
+        }
+    }
+
+    /// The rename as a coloured diff of the old and new path.
+    pub fn pretty_diff(&self) -> impl Display {
+        struct PrettyDiff(Rename);
+        impl Display for PrettyDiff {
+            fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+                let diff_changes = calculate_text_diff(
+                    &self.0.original.display().to_string(),
+                    &self.0.new.display().to_string(),
+                );
+
+                // Print old.
+                write!(f, "{}", Colour::Red.paint("- "))?;
+                for change in &diff_changes {
+                    match change {
+                        TextDiff::Removed(old) => {
+                            write!(f, "{}", Colour::Red.paint(old))?;
+                        }
+                        TextDiff::Unchanged(same) => {
+                            write!(f, "{}", same)?;
+                        }
+                        _ => (),
+                    }
+                }
+                writeln!(f)?;
+
+                // Print new.
+                write!(f, "{}", Colour::Green.paint("+ "))?;
+                for change in &diff_changes {
+                    match change {
+                        TextDiff::New(new) => {
+                            write!(f, "{}", Colour::Green.paint(new))?;
+                        }
+                        TextDiff::Unchanged(same) => {
+                            write!(f, "{}", same)?;
+                        }
+                        _ => (),
+                    }
+                }
+
+                Ok(())
+            }
+        }
+        PrettyDiff(self.clone())
+    }
+
+    /// The rename as `original -> new`.
+    pub fn plain_diff(&self) -> impl Display {
+        struct PlainDiff(Rename);
+        impl Display for PlainDiff {
+            fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+                write!(
+                    f,
+                    "{} -> {}",
+                    self.0.original.display(),
+                    self.0.new.display()
+                )
+            }
+        }
+        PlainDiff(self.clone())
+    }
+}
+impl Display for Rename {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.plain_diff().fmt(f)
+    }
+}
+
+/// Serialize paths as strings, or as raw bytes if they are not valid UTF-8.
+pub mod path_serde {
+    use std::path::{Path, PathBuf};
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::escape::{os_str_bytes, os_string_from_bytes};
+
+    #[derive(Serialize)]
+    #[serde(untagged)]
+    enum Repr<'a> {
+        Str(&'a str),
+        Bytes(Vec<u8>),
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OwnedRepr {
+        Str(String),
+        Bytes(Vec<u8>),
+    }
+
+    pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+        match path.to_str() {
+            Some(s) => Repr::Str(s),
+            None => Repr::Bytes(os_str_bytes(path.as_os_str()).into_owned()),
+        }
+        .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+        Ok(match OwnedRepr::deserialize(deserializer)? {
+            OwnedRepr::Str(s) => s.into(),
+            OwnedRepr::Bytes(b) => os_string_from_bytes(b).into(),
+        })
+    }
+}
+
+#[derive(Error, Debug, Clone)]
+pub enum RenamerError {
+    #[error("No replacements found.")]
+    NoReplacementsFound,
+    #[error("Unequal number of files.")]
+    UnequalLines,
+    #[error("Duplicate output files.")]
+    DuplicateOutput,
+    #[error("Invalid file name on line {0}: {1}.")]
+    InvalidLine(usize, String),
+    #[error("Directories cannot be moved into each other.")]
+    NestedCycle,
+    #[error("Nonexistent input files.")]
+    NonexistentFiles(Vec<PathBuf>),
+    #[error("Duplicate input files.")]
+    DuplicateInput,
+}
+
+/// Pair up the original file names with the edited ones, ignoring unchanged
+/// names.
+///
+/// Renames inside renamed directories are rebased onto the new directory, see
+/// [`plan::rebase_nested`]. Fails if the lists differ in length, nothing
+/// changed or two files would end up with the same name.
+pub fn find_renames(
+    old_lines: &[PathBuf],
+    new_lines: &[PathBuf],
+) -> Result<Vec<Rename>, RenamerError> {
+    if old_lines.len() != new_lines.len() {
+        return Err(RenamerError::UnequalLines);
+    }
+    let renames: Vec<_> = old_lines
+        .iter()
+        .zip(new_lines)
+        .filter_map(|(original, new)| {
+            if original == new {
+                None
+            } else {
+                Some(Rename::new(original, new))
+            }
+        })
+        .collect();
+
+    if renames.is_empty() {
+        return Err(RenamerError::NoReplacementsFound);
+    }
+
+    let renames = rebase_nested(renames)?;
+    has_duplicate_renames(&renames)?;
+
+    Ok(renames)
+}
+
+/// Check for duplicate new files.
+fn has_duplicate_renames(replacements: &[Rename]) -> Result<(), RenamerError> {
+    let mut set = HashSet::new();
+
+    for item in replacements {
+        if !set.insert(item.new.clone()) {
+            return Err(RenamerError::DuplicateOutput);
+        }
+    }
+
+    Ok(())
+}
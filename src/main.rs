@@ -1,38 +1,65 @@
 use ansi_term::Colour;
-use clap::Parser;
+use clap::{ArgGroup, Parser};
 
 use anyhow::{bail, Context};
-use dialoguer::Select;
-use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use dialoguer::{Confirm, Select};
+use std::collections::HashMap;
 use std::env;
+use std::ffi::OsString;
 use std::fmt::{Display, Formatter};
 use std::fs;
-use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::io::{self, IsTerminal, Read, Seek, SeekFrom, Write};
 use std::iter::zip;
 use std::path::{Path, PathBuf};
-use std::process::Command;
-
-use thiserror::Error;
-
-mod text_diff;
-use text_diff::{calculate_text_diff, TextDiff};
+use std::process::{Command, Stdio};
+use std::thread;
+
+use pipe_rename::backend::{BackendKind, CommandBackend, Progress, RenameBackend};
+use pipe_rename::conflict::{self, ConflictPolicy, Resolution};
+use pipe_rename::escape::{os_str_bytes, os_string_from_bytes, Escape};
+use pipe_rename::execute::{self, execute_renames, Batch, TrashMoves};
+use pipe_rename::format::{read_plan, write_plan, PlanFormat};
+use pipe_rename::git::IndexMoves;
+use pipe_rename::history::{self, check_restorable, state_dir, History};
+use pipe_rename::output::{Event, Events, OutputMode};
+use pipe_rename::plan::{self, blocking_conflicts, Conflict};
+use pipe_rename::sort::{sort_files, SortKey};
+use pipe_rename::substitute::{substitute, Substitution};
+use pipe_rename::trash::{self, restore_moves};
+use pipe_rename::walk::{walk_dir, WalkOptions};
+use pipe_rename::{find_renames, Rename, RenamerError};
 
 #[derive(Parser, Debug)]
 #[clap(
     version = env!("CARGO_PKG_VERSION"),
     author = "Marcus B. <me@mbuffett.com>",
     about = "https://github.com/marcusbuffett/pipe-rename",
-    long_about = "Takes a list of files and renames/moves them by piping them through an external editor"
+    long_about = "Takes a list of files and renames/moves them by piping them through an external editor",
+    group(ArgGroup::new("history-mode").args(&["undo", "redo", "history"]).multiple(true))
 )]
 struct Opts {
-    #[clap(name = "FILES")]
-    files: Vec<String>,
+    #[clap(name = "FILES", parse(from_os_str))]
+    files: Vec<PathBuf>,
 
     /// Use a custom rename command, like 'git mv'
     #[clap(short = 'c', long, value_name = "COMMAND")]
     rename_command: Option<String>,
 
+    /// Move the git index entries of tracked files along with them, like
+    /// `git mv`, in one batch. Cannot be used with `--backend git`
+    #[clap(long, conflicts_with = "rename-command")]
+    git: bool,
+
+    /// How files are moved
+    #[clap(
+        long,
+        arg_enum,
+        value_name = "BACKEND",
+        default_value = "fs",
+        conflicts_with = "rename-command"
+    )]
+    backend: BackendKind,
+
     /// Specify what editor to use
     #[clap(short = 'e', long)]
     editor: Option<String>,
@@ -49,9 +76,25 @@ struct Opts {
     #[clap(short, long)]
     force: bool,
 
-    /// Undo the previous renaming operation
-    #[clap(short, long)]
-    undo: bool,
+    /// Resolve renames onto existing files instead of refusing them
+    #[clap(long, arg_enum, value_name = "POLICY", conflicts_with = "force")]
+    on_conflict: Option<ConflictPolicy>,
+
+    /// Undo the previous renaming operation, or the one with the given id
+    #[clap(short, long, value_name = "N")]
+    undo: Option<Option<u64>>,
+
+    /// Redo the most recently undone operation
+    #[clap(long, conflicts_with = "undo")]
+    redo: bool,
+
+    /// List previous renaming operations
+    #[clap(long, conflicts_with_all = &["undo", "redo"])]
+    history: bool,
+
+    /// Include operations from other directories in undo, redo and history
+    #[clap(long, requires = "history-mode")]
+    any_dir: bool,
 
     /// Create parent directories if needed
     #[clap(short, long)]
@@ -64,190 +107,186 @@ struct Opts {
     /// Only rename filenames
     #[clap(short = 'n', long)]
     filenames_only: bool,
-}
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct Rename {
-    original: PathBuf,
-    new: PathBuf,
+    /// Read NUL-separated file names from stdin, like `find -print0`, escaping
+    /// names with line breaks in the editor
+    #[clap(short = '0', long)]
+    null: bool,
+
+    /// Print the new file names separated by NUL after renaming
+    #[clap(long)]
+    print0: bool,
+
+    /// Escape file names in the editor buffer, so every name fits on one line
+    #[clap(long, arg_enum, value_name = "STYLE")]
+    escape: Option<Escape>,
+
+    #[clap(flatten)]
+    walk: WalkOptions,
+
+    /// Sort the file names, naturally by default for expanded directories
+    #[clap(long, arg_enum, value_name = "KEY")]
+    sort: Option<SortKey>,
+
+    /// Reverse the order of the file names
+    #[clap(long)]
+    reverse: bool,
+
+    /// Rename with a sed-like substitution instead of opening the editor,
+    /// can be given multiple times
+    #[clap(
+        long,
+        value_name = "s/PATTERN/REPLACEMENT/FLAGS",
+        multiple_occurrences = true
+    )]
+    regex: Vec<Substitution>,
+
+    /// Pipe the file names through COMMAND instead of opening the editor,
+    /// reading the new names from its output
+    #[clap(long, value_name = "COMMAND", conflicts_with = "regex")]
+    filter: Option<String>,
+
+    /// Prefix every line in the editor with an id, so lines can be sorted and
+    /// moved around freely
+    #[clap(long)]
+    line_ids: bool,
+
+    /// Delete files whose line is removed in the editor, keeping them in the
+    /// trash for undo. Implies --line-ids
+    #[clap(long, conflicts_with = "apply")]
+    allow_delete: bool,
+
+    /// Only print what would be renamed, without renaming anything
+    #[clap(long)]
+    dry_run: bool,
+
+    /// Print the plan of a dry run in a machine-readable format
+    #[clap(long, arg_enum, value_name = "FORMAT", requires = "dry-run")]
+    format: Option<PlanFormat>,
+
+    /// Rename according to a plan written with `--dry-run --format json`,
+    /// instead of editing file names
+    #[clap(
+        long,
+        value_name = "PLAN",
+        parse(from_os_str),
+        conflicts_with_all = &["FILES", "regex", "filter"]
+    )]
+    apply: Option<PathBuf>,
+
+    /// Report progress as text, or as newline-delimited JSON events for
+    /// integrations
+    #[clap(
+        long,
+        arg_enum,
+        value_name = "MODE",
+        default_value = "text",
+        conflicts_with_all = &["print0", "format"]
+    )]
+    output: OutputMode,
 }
 
-impl Rename {
-    fn new(original: &str, new: &str) -> Self {
-        // Expand ~ if applicable.
-        let mut new = new.to_string();
-        if let Ok(home) = env::var("HOME") {
-            if new.starts_with("~/") {
-                new = new.replacen('~', &home, 1);
-            }
-        }
-
-        Rename {
-            original: original.into(),
-            new: new.into(),
-        }
-    }
-
-    fn pretty_diff(&self) -> impl Display {
-        struct PrettyDiff(Rename);
-        impl Display for PrettyDiff {
-            fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-                let diff_changes = calculate_text_diff(
-                    &self.0.original.display().to_string(),
-                    &self.0.new.display().to_string(),
-                );
-
-                // Print old.
-                write!(f, "{}", Colour::Red.paint("- "))?;
-                for change in &diff_changes {
-                    match change {
-                        TextDiff::Removed(old) => {
-                            write!(f, "{}", Colour::Red.paint(old))?;
-                        }
-                        TextDiff::Unchanged(same) => {
-                            write!(f, "{}", same)?;
-                        }
-                        _ => (),
-                    }
-                }
-                writeln!(f)?;
-
-                // Print new.
-                write!(f, "{}", Colour::Green.paint("+ "))?;
-                for change in &diff_changes {
-                    match change {
-                        TextDiff::New(new) => {
-                            write!(f, "{}", Colour::Green.paint(new))?;
-                        }
-                        TextDiff::Unchanged(same) => {
-                            write!(f, "{}", same)?;
-                        }
-                        _ => (),
-                    }
-                }
-
-                Ok(())
-            }
-        }
-        PrettyDiff(self.clone())
-    }
-
-    fn plain_diff(&self) -> impl Display {
-        struct PlainDiff(Rename);
-        impl Display for PlainDiff {
-            fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-                write!(
-                    f,
-                    "{} -> {}",
-                    self.0.original.display(),
-                    self.0.new.display()
-                )
-            }
-        }
-        PlainDiff(self.clone())
-    }
-}
-impl Display for Rename {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        self.plain_diff().fmt(f)
-    }
-}
-
-#[derive(Error, Debug, Clone)]
-pub enum RenamerError {
-    #[error("No replacements found.")]
-    NoReplacementsFound,
-    #[error("Unequal number of files.")]
-    UnequalLines,
-    #[error("Duplicate output files.")]
-    DuplicateOutput,
-}
-
-fn find_renames(old_lines: &[String], new_lines: &[String]) -> Result<Vec<Rename>, RenamerError> {
-    if old_lines.len() != new_lines.len() {
-        return Err(RenamerError::UnequalLines);
-    }
-    let renames: Vec<_> = old_lines
-        .iter()
-        .zip(new_lines)
-        .filter_map(|(original, new)| {
-            if original == new {
-                None
-            } else {
-                Some(Rename::new(original, new))
-            }
-        })
-        .collect();
-
-    if renames.is_empty() {
-        return Err(RenamerError::NoReplacementsFound);
-    }
-
-    has_duplicate_renames(&renames)?;
-
-    Ok(renames)
-}
-
-/// Check for duplicate new files.
-fn has_duplicate_renames(replacements: &[Rename]) -> Result<(), RenamerError> {
-    let mut set = HashSet::new();
-
-    for item in replacements {
-        if !set.insert(item.new.clone()) {
-            return Err(RenamerError::DuplicateOutput);
-        }
-    }
-
-    Ok(())
-}
-
-fn get_input(files: Vec<String>) -> anyhow::Result<Vec<String>> {
+fn get_input(files: Vec<PathBuf>, null: bool) -> anyhow::Result<Vec<PathBuf>> {
     if !files.is_empty() {
         return Ok(files);
     }
 
+    // Read raw bytes, file names are not necessarily valid UTF-8.
     let input = {
-        let mut buffer = String::new();
-        io::stdin().read_to_string(&mut buffer)?;
+        let mut buffer = Vec::new();
+        io::stdin().read_to_end(&mut buffer)?;
         buffer
     };
     if input.is_empty() {
         bail!("No input files on stdin or as args.");
     }
 
-    Ok(input.lines().map(|f| f.to_string()).collect())
+    let names: Vec<&[u8]> = if null {
+        input
+            .split(|&b| b == b'\0')
+            .filter(|f| !f.is_empty())
+            .collect()
+    } else {
+        // Like `str::lines`, empty lines are kept as names, which are then
+        // reported as nonexistent.
+        let input = input.strip_suffix(b"\n").unwrap_or(&input);
+        input
+            .split(|&b| b == b'\n')
+            .map(|f| f.strip_suffix(b"\r").unwrap_or(f))
+            .collect()
+    };
+    Ok(names
+        .into_iter()
+        .map(|f| os_string_from_bytes(f.to_vec()).into())
+        .collect())
 }
 
-fn get_input_files(files: Vec<String>) -> anyhow::Result<Vec<String>> {
-    let mut input_files = get_input(files)?;
+fn get_input_files(
+    files: Vec<PathBuf>,
+    null: bool,
+    walk: &WalkOptions,
+    sort: Option<SortKey>,
+    reverse: bool,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let mut input_files = get_input(files, null)?;
+    let dots = [Path::new("."), Path::new("..")];
+    let mut sort = sort;
+    if walk.recursive {
+        let mut expanded = vec![];
+        for file in input_files {
+            if file.symlink_metadata().is_ok_and(|m| m.is_dir()) {
+                expanded.extend(walk_dir(&file, walk)?);
+            } else {
+                expanded.push(file);
+            }
+        }
+        input_files = expanded;
+        sort = sort.or(Some(SortKey::Natural));
     // This is a special case where we want to expand `.` and `..`.
-    let dots = &[".", ".."];
-    if input_files.len() == 1 && dots.contains(&input_files[0].as_str()) {
-        input_files = expand_dir(&input_files[0])?;
+    } else if input_files.len() == 1 && dots.contains(&input_files[0].as_path()) {
+        input_files = if walk.respect_gitignore {
+            let shallow = WalkOptions {
+                max_depth: Some(1),
+                hidden: true,
+                respect_gitignore: true,
+                ..Default::default()
+            };
+            walk_dir(&input_files[0], &shallow)?
+        } else {
+            expand_dir(&input_files[0])?
+        };
+        sort = sort.or(Some(SortKey::Natural));
     }
     if input_files.is_empty() {
         bail!("No input files on stdin or as args.");
     }
 
+    if let Some(key) = sort {
+        sort_files(&mut input_files, key);
+    }
+    if reverse {
+        input_files.reverse();
+    }
+
     Ok(input_files)
 }
 
-fn expand_dir(path: &str) -> anyhow::Result<Vec<String>, io::Error> {
+fn expand_dir(path: &Path) -> anyhow::Result<Vec<PathBuf>, io::Error> {
     Ok(fs::read_dir(path)?
-        .filter_map(|e| {
-            e.ok()
-                .and_then(|e| e.path().into_os_string().into_string().ok())
-        })
+        .filter_map(|e| e.ok().map(|e| e.path()))
         .collect())
 }
 
+fn has_line_break(path: &Path) -> bool {
+    os_str_bytes(path.as_os_str())
+        .iter()
+        .any(|&b| b == b'\n' || b == b'\r')
+}
+
 /// Split path into directory path and filename.
-fn path_and_file_name(line: &String) -> Option<(PathBuf, String)> {
-    let path = PathBuf::from(line);
+fn path_and_file_name(path: &Path) -> Option<(PathBuf, OsString)> {
     let dirname = path.parent().map(PathBuf::from);
-    let file_name = path
-        .file_name()
-        .and_then(|f| f.to_os_string().into_string().ok());
+    let file_name = path.file_name().map(OsString::from);
 
     match (dirname, file_name) {
         (Some(d), Some(f)) => Some((d, f)),
@@ -255,34 +294,150 @@ fn path_and_file_name(line: &String) -> Option<(PathBuf, String)> {
     }
 }
 
+/// The contents of the buffer handed to the editor or filter, one name per line.
+struct Buffer {
+    /// The entry each line was written for, with the directory it was taken
+    /// from with `--filenames-only`.
+    lines: Vec<(usize, Option<PathBuf>)>,
+    /// The number of entries, including deleted ones.
+    len: usize,
+    escape: Option<Escape>,
+    /// Whether lines are prefixed with the number of their entry, so that
+    /// they are matched up even when reordered or deleted.
+    line_ids: bool,
+    text: String,
+}
+
+impl Buffer {
+    fn new(
+        entries: &[Option<PathBuf>],
+        filenames_only: bool,
+        escape: Option<Escape>,
+        line_ids: bool,
+    ) -> anyhow::Result<Self> {
+        let width = entries.len().to_string().len().max(4);
+        let mut lines = vec![];
+        let mut text = vec![];
+        for (i, entry) in entries.iter().enumerate() {
+            let path = match entry {
+                Some(path) => path,
+                None => continue,
+            };
+            let (dir, name) = if filenames_only {
+                match path_and_file_name(path) {
+                    Some((dir, name)) => (Some(dir), name),
+                    // A missing line would delete the file.
+                    None if line_ids => {
+                        return Err(RenamerError::InvalidLine(i + 1, "no file name".into()).into())
+                    }
+                    None => continue,
+                }
+            } else {
+                (None, path.as_os_str().into())
+            };
+
+            let line = match escape {
+                Some(escape) => escape
+                    .encode(&name)
+                    .map_err(|e| RenamerError::InvalidLine(i + 1, e)),
+                None => name
+                    .to_str()
+                    .map(ToString::to_string)
+                    .ok_or_else(|| RenamerError::InvalidLine(i + 1, "not valid UTF-8".into())),
+            }?;
+            text.push(if line_ids {
+                format!("{:0width$}\t{}", i + 1, line, width = width)
+            } else {
+                line
+            });
+            lines.push((i, dir));
+        }
+
+        Ok(Buffer {
+            lines,
+            len: entries.len(),
+            escape,
+            line_ids,
+            text: text.join("\n"),
+        })
+    }
+
+    /// Parse the edited text back into paths.
+    ///
+    /// With line ids, there is one path per entry, in the original order, and
+    /// `None` for deleted lines. Otherwise there is one path per line.
+    fn parse(self, edited: &str) -> anyhow::Result<Vec<Option<PathBuf>>> {
+        let dirs: HashMap<usize, Option<PathBuf>> = self.lines.iter().cloned().collect();
+        let mut parsed = vec![None; if self.line_ids { self.len } else { 0 }];
+
+        for (i, line) in edited.lines().enumerate() {
+            let (entry, line) = if self.line_ids {
+                if line.is_empty() {
+                    continue;
+                }
+                let (id, name) = line
+                    .split_once('\t')
+                    .and_then(|(id, name)| Some((id.parse::<usize>().ok()?, name)))
+                    .ok_or_else(|| RenamerError::InvalidLine(i + 1, "missing line id".into()))?;
+                let entry = id.checked_sub(1).filter(|entry| dirs.contains_key(entry));
+                match entry {
+                    Some(entry) if parsed[entry].is_none() => (entry, name),
+                    Some(_) => {
+                        bail!(RenamerError::InvalidLine(
+                            i + 1,
+                            format!("duplicate line id {}", id)
+                        ))
+                    }
+                    None => bail!(RenamerError::InvalidLine(
+                        i + 1,
+                        format!("unknown line id {}", id)
+                    )),
+                }
+            } else {
+                match self.lines.get(i) {
+                    Some((entry, _)) => (*entry, line),
+                    // Extra lines are reported as unequal.
+                    None => (self.len, line),
+                }
+            };
+
+            let name: OsString = match self.escape {
+                Some(escape) => escape
+                    .decode(line)
+                    .map_err(|e| RenamerError::InvalidLine(i + 1, e))?,
+                None => line.into(),
+            };
+            // Add the path back to the filename.
+            let path = match dirs.get(&entry) {
+                Some(Some(dir)) => dir.join(name),
+                _ => PathBuf::from(name),
+            };
+            if self.line_ids {
+                parsed[entry] = Some(path);
+            } else {
+                parsed.push(Some(path));
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
 fn open_editor(
-    input_files: &[String],
+    entries: &[Option<PathBuf>],
     editor_string: &str,
     filenames_only: bool,
-) -> anyhow::Result<Vec<String>> {
+    escape: Option<Escape>,
+    line_ids: bool,
+) -> anyhow::Result<Vec<Option<PathBuf>>> {
     let mut tmpfile = tempfile::Builder::new()
         .prefix("renamer-")
         .suffix(".txt")
         .tempfile()
         .context("Could not create temp file")?;
 
-    let mut components: Vec<(PathBuf, String)> = vec![];
-
-    if filenames_only {
-        components = input_files.iter().filter_map(path_and_file_name).collect();
-
-        write!(
-            tmpfile,
-            "{}",
-            components
-                .iter()
-                .map(|(_, filename)| filename.to_string())
-                .collect::<Vec<_>>()
-                .join("\n")
-        )?;
-    } else {
-        write!(tmpfile, "{}", input_files.join("\n"))?;
-    }
+    let buffer = Buffer::new(entries, filenames_only, escape, line_ids)?;
+    write!(tmpfile, "{}", buffer.text)?;
 
     let editor_parsed = shell_words::split(editor_string)
         .expect("failed to parse command line flags in EDITOR command");
@@ -303,122 +458,234 @@ fn open_editor(
         bail!("Editor terminated unexpectedly.");
     }
 
-    let changes: Vec<_> = fs::read_to_string(&tmpfile)?
-        .lines()
-        .map(|f| f.to_string())
-        .collect();
+    buffer.parse(&fs::read_to_string(&tmpfile)?)
+}
 
-    // Add the path back to the filename.
-    if filenames_only {
-        return Ok(zip(components, changes)
-            .map(|(parts, file_name)| parts.0.join(file_name).display().to_string())
-            .collect());
+/// Pipe the buffer through a command and read the new names from its output.
+fn run_filter(
+    entries: &[Option<PathBuf>],
+    command: &str,
+    filenames_only: bool,
+    escape: Option<Escape>,
+    line_ids: bool,
+) -> anyhow::Result<Vec<Option<PathBuf>>> {
+    let buffer = Buffer::new(entries, filenames_only, escape, line_ids)?;
+
+    let command_parsed = shell_words::split(command)
+        .with_context(|| format!("Could not parse filter command: '{}'", command))?;
+    if command_parsed.is_empty() {
+        bail!("Filter command is empty.");
+    }
+    let mut child = Command::new(&command_parsed[0])
+        .args(&command_parsed[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to execute filter command: '{}'", command))?;
+
+    // Write from another thread, so a filter producing output before it has
+    // read all of its input cannot block.
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let text = format!("{}\n", buffer.text);
+    let writer = thread::spawn(move || stdin.write_all(text.as_bytes()));
+
+    let output = child.wait_with_output()?;
+    // The filter may exit without reading everything, which is fine.
+    let _ = writer.join();
+    if !output.status.success() {
+        bail!("Filter command exited with {}.", output.status);
     }
 
-    Ok(changes)
+    let edited = String::from_utf8(output.stdout).context("Filter output is not valid UTF-8")?;
+    buffer.parse(&edited)
 }
 
-fn check_for_existing_files(replacements: &[Rename], force: bool) -> anyhow::Result<()> {
+/// Produce the new names by applying substitutions, like editing each line of
+/// the buffer would.
+fn substitute_names(
+    entries: &[Option<PathBuf>],
+    substitutions: &[Substitution],
+    filenames_only: bool,
+) -> Vec<Option<PathBuf>> {
+    entries
+        .iter()
+        .map(|entry| {
+            let file = entry.as_ref()?;
+            Some(match path_and_file_name(file) {
+                Some((dir, file_name)) if filenames_only => {
+                    dir.join(substitute(substitutions, &file_name))
+                }
+                _ => substitute(substitutions, file.as_os_str()).into(),
+            })
+        })
+        .collect()
+}
+
+fn check_for_existing_files(
+    replacements: &[Rename],
+    deleted: &[PathBuf],
+    force: bool,
+    out: &mut dyn Write,
+    events: &Events,
+) -> anyhow::Result<()> {
     // Skip check if forcing renames.
     if force {
         return Ok(());
     }
 
-    let replacements_over_existing_files: Vec<_> = replacements
-        .iter()
-        .filter(|replacement| Path::new(&replacement.new).exists())
-        .collect();
+    let replacements_over_existing_files = blocking_conflicts(replacements, deleted);
+    for conflict in &replacements_over_existing_files {
+        events.emit(&Event::Conflict(conflict.rename))?;
+    }
     if !replacements_over_existing_files.is_empty() {
-        println!("The following replacements overwrite existing files:");
-        for replacement in &replacements_over_existing_files {
-            println!("{}", Colour::Red.paint(replacement.to_string()));
+        writeln!(out, "The following replacements overwrite existing files:")?;
+        for conflict in &replacements_over_existing_files {
+            writeln!(out, "{}", Colour::Red.paint(conflict.rename.to_string()))?;
         }
-        println!();
+        writeln!(out)?;
         bail!("Refusing to overwrite existing files.");
     }
 
     Ok(())
 }
 
-fn check_input_files(input_files: &[String]) -> anyhow::Result<()> {
-    let nonexisting_files: Vec<_> = input_files
+/// Resolve renames onto existing files with the policy given by
+/// `--on-conflict`.
+fn resolve_with_policy(
+    replacements: Vec<Rename>,
+    deleted: &[PathBuf],
+    opts: &Opts,
+    events: &Events,
+) -> anyhow::Result<Vec<Rename>> {
+    let policy = match opts.on_conflict {
+        Some(policy) => policy,
+        None => return Ok(replacements),
+    };
+    if policy == ConflictPolicy::Ask && opts.assume_yes {
+        bail!("Cannot ask about conflicts with --yes.");
+    }
+
+    conflict::resolve_with_policy(replacements, deleted, policy, events, ask_resolution)
+}
+
+fn ask_resolution(conflict: &Conflict) -> anyhow::Result<Resolution> {
+    let choices = [
+        ("Skip", Resolution::Skip),
+        ("Overwrite", Resolution::Overwrite),
+        ("Back up the existing file", Resolution::Backup),
+        ("Number the new name", Resolution::Number),
+    ];
+    let selection = Select::new()
+        .with_prompt(format!(
+            "{} overwrites {}",
+            conflict.rename,
+            conflict.existing.display()
+        ))
+        .default(0)
+        .items(&choices.iter().map(|(label, _)| label).collect::<Vec<_>>())
+        .interact()?;
+    Ok(choices[selection].1)
+}
+
+/// Where the input files end up after the renames, for `--print0`.
+///
+/// Files that are not renamed themselves follow their closest renamed
+/// directory, and skipped files keep their name.
+fn resolved_names(files: &[PathBuf], replacements: &[Rename]) -> Vec<PathBuf> {
+    let renamed: HashMap<&Path, &Path> = replacements
         .iter()
-        .filter(|input_file| !Path::new(input_file).exists())
+        .map(|r| (r.original.as_path(), r.new.as_path()))
         .collect();
+    files
+        .iter()
+        .map(|file| {
+            file.ancestors()
+                .find_map(|dir| {
+                    let new = renamed.get(dir)?;
+                    let rel = file.strip_prefix(dir).expect("ancestor is a prefix");
+                    Some(if rel.as_os_str().is_empty() {
+                        new.to_path_buf()
+                    } else {
+                        new.join(rel)
+                    })
+                })
+                .unwrap_or_else(|| file.clone())
+        })
+        .collect()
+}
 
-    if !nonexisting_files.is_empty() {
-        println!("The following input files do not exist:");
-        for file in nonexisting_files {
-            println!("{}", Colour::Red.paint(file));
+fn check_input_files(input_files: &[PathBuf], out: &mut dyn Write) -> anyhow::Result<()> {
+    match plan::check_input_files(input_files) {
+        Err(RenamerError::NonexistentFiles(files)) => {
+            writeln!(out, "The following input files do not exist:")?;
+            for file in &files {
+                writeln!(out, "{}", Colour::Red.paint(file.display().to_string()))?;
+            }
+            writeln!(out)?;
+            Err(RenamerError::NonexistentFiles(files).into())
         }
-        println!();
-        bail!("Nonexistent input files.");
+        result => Ok(result?),
     }
+}
 
-    let mut set = HashSet::new();
+fn print_replacements(
+    replacements: &[Rename],
+    deleted: &[PathBuf],
+    pretty: bool,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    if !replacements.is_empty() || deleted.is_empty() {
+        writeln!(
+            out,
+            "{}",
+            Colour::Yellow.paint("The following replacements were found:")
+        )?;
+        writeln!(out)?;
 
-    for item in input_files {
-        if !set.insert(item.clone()) {
-            bail!("Duplicate input files.");
+        if pretty {
+            let diff_output = replacements
+                .iter()
+                .map(|repl| repl.pretty_diff().to_string())
+                .collect::<Vec<String>>()
+                .join("\n\n"); // Leave a blank line between pretty file diffs
+            writeln!(out, "{}", diff_output)?;
+        } else {
+            for replacement in replacements {
+                writeln!(out, "{}", Colour::Green.paint(replacement.to_string()))?;
+            }
         }
+        writeln!(out)?;
     }
 
-    Ok(())
-}
-
-fn print_replacements(replacements: &Vec<Rename>, pretty: bool) {
-    println!(
-        "{}",
-        Colour::Yellow.paint("The following replacements were found:")
-    );
-    println!();
-
-    if pretty {
-        let diff_output = replacements
-            .iter()
-            .map(|repl| repl.pretty_diff().to_string())
-            .collect::<Vec<String>>()
-            .join("\n\n"); // Leave a blank line between pretty file diffs
-        println!("{}", diff_output);
-    } else {
-        for replacement in replacements {
-            println!("{}", Colour::Green.paint(replacement.to_string()));
+    if !deleted.is_empty() {
+        writeln!(
+            out,
+            "{}",
+            Colour::Yellow.paint("The following files will be deleted:")
+        )?;
+        writeln!(out)?;
+        for file in deleted {
+            writeln!(out, "{}", Colour::Red.paint(file.display().to_string()))?;
         }
+        writeln!(out)?;
     }
-    println!();
+    Ok(())
 }
 
-fn execute_renames(
-    replacements: &Vec<Rename>,
-    rename_command: Option<String>,
-    parents: bool,
-) -> anyhow::Result<()> {
-    for replacement in replacements {
-        if let Some(ref cmd) = rename_command {
-            let cmd_parsed = shell_words::split(cmd)
-                .expect("failed to parse command line flags in rename command");
-            subprocess::Exec::cmd(&cmd_parsed[0])
-                .args(&cmd_parsed[1..])
-                .arg(&replacement.original)
-                .arg(&replacement.new)
-                .join()?;
-        } else {
-            match fs::rename(&replacement.original, &replacement.new) {
-                Ok(()) => (),
-                // If renaming fails, optionally create parent directories and try again.
-                Err(e) if parents && e.kind() == std::io::ErrorKind::NotFound => {
-                    let dir = &replacement.new.parent();
-                    if let Some(dir) = dir {
-                        fs::create_dir_all(dir)?;
-                        fs::rename(&replacement.original, &replacement.new)?;
-                    }
-                }
-                Err(e) => return Err(e.into()),
-            };
-        }
+/// Confirm deleting files separately from renaming, since a line is easily
+/// removed by accident.
+fn confirm_deletions(deleted: &[PathBuf], yes: bool) -> anyhow::Result<bool> {
+    if deleted.is_empty() || yes {
+        return Ok(true);
     }
 
-    Ok(())
+    Ok(Confirm::new()
+        .with_prompt(format!(
+            "Delete {} files? They are kept in the trash for undo.",
+            deleted.len()
+        ))
+        .default(false)
+        .interact()?)
 }
 
 fn prompt(selections: &[MenuItem], yes: bool) -> anyhow::Result<&MenuItem> {
@@ -457,90 +724,393 @@ impl Display for MenuItem {
     }
 }
 
-fn make_absolute(path: PathBuf) -> anyhow::Result<PathBuf> {
-    if path.is_relative() {
-        Ok(std::env::current_dir()?.join(path))
-    } else {
-        Ok(path)
+/// Execute the renames with the chosen backend, see [`execute::run`].
+///
+/// Returns whether any files were touched, which is not the case when the
+/// steps are only recorded.
+fn run_renames(
+    batch: &Batch,
+    opts: &Opts,
+    events: &Events,
+    out: &mut dyn Write,
+) -> anyhow::Result<bool> {
+    let mut backend: Box<dyn RenameBackend> = match &opts.rename_command {
+        Some(command) => Box::new(CommandBackend::new(command)?),
+        None => opts.backend.create(copy_progress(events)),
+    };
+    if backend.recorded_steps().is_some() {
+        // Recorded steps are not reported as done, nothing is renamed.
+        execute_renames(
+            batch.renames,
+            backend.as_mut(),
+            batch.parents,
+            &Events::default(),
+        )?;
+        writeln!(out, "Recorded steps, no files were renamed:")?;
+        for step in backend.recorded_steps().unwrap_or_default() {
+            writeln!(out, "{}", step)?;
+        }
+        return Ok(false);
     }
+    execute::run(batch, backend.as_mut(), events, &mut print_warning)?;
+    Ok(true)
 }
 
-fn write_undo_renames(backup_file: PathBuf, replacements: Vec<Rename>) -> anyhow::Result<()> {
-    let undo_replacements = replacements
-        .into_iter()
-        .map(|r| {
-            // Make paths absolute to that undo does not depend on CWD.
-            let original = make_absolute(r.original)?;
-            let new = make_absolute(r.new)?;
-
-            Ok(Rename {
-                // Swap original and new to get undo replacements.
-                original: new,
-                new: original,
-            })
-        })
-        .collect::<anyhow::Result<Vec<_>>>()?;
+fn print_warning(warning: &str) {
+    eprintln!("Warning: {}", warning);
+}
 
-    let file = fs::File::create(backup_file)?;
-    serde_json::to_writer(file, &undo_replacements)?;
-    Ok(())
+/// Show the progress of copying large files across file systems on a terminal.
+fn copy_progress(events: &Events) -> Option<Progress> {
+    if events.enabled() || !io::stderr().is_terminal() {
+        return None;
+    }
+    Some(Box::new(|path: &Path, copied: u64, size: u64| {
+        const MIB: u64 = 1024 * 1024;
+        eprint!(
+            "\rCopying {}: {} of {} MiB",
+            path.display(),
+            copied / MIB,
+            size / MIB
+        );
+        if copied == size {
+            eprintln!();
+        }
+    }))
+}
+
+/// Find the tracked files to move in the git index, with `--git`.
+fn find_index_moves(replacements: &[Rename], git: bool) -> anyhow::Result<Option<IndexMoves>> {
+    if !git {
+        return Ok(None);
+    }
+    IndexMoves::find(replacements)
 }
 
-fn load_undo_renames(backup_file: PathBuf) -> anyhow::Result<Vec<Rename>> {
-    let file = fs::File::open(&backup_file);
-    let file = match file {
-        Ok(f) => f,
-        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-            bail!("No undo information found.")
+fn print_dry_run(
+    replacements: &[Rename],
+    deleted: &[PathBuf],
+    opts: &Opts,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    match opts.format {
+        Some(_) if !deleted.is_empty() => bail!("Deleting files cannot be written as a plan."),
+        Some(format) => write_plan(
+            replacements,
+            format,
+            opts.rename_command
+                .as_deref()
+                .or_else(|| opts.backend.command()),
+            opts.parents,
+            &mut io::stdout().lock(),
+        )?,
+        None => {
+            if !opts.quiet {
+                print_replacements(replacements, deleted, opts.pretty_diff, out)?;
+            }
+            writeln!(out, "Dry run, no files were renamed.")?;
         }
-        Err(e) => return Err(e.into()),
-    };
-    let undo_replacements: Vec<Rename> = serde_json::from_reader(file)?;
-    for replacement in &undo_replacements {
-        if !replacement.original.exists() {
-            bail!(
-                "Undo not possible. \"{}\" is missing.",
-                replacement.original.display()
-            );
+    }
+    Ok(())
+}
+
+/// Write the new file names to stdout, separated by NUL.
+fn print_new_files(files: &[PathBuf]) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    for file in files {
+        stdout.write_all(&os_str_bytes(file.as_os_str()))?;
+        stdout.write_all(b"\0")?;
+    }
+    stdout.flush()
+}
+
+/// Record a finished operation in the history.
+///
+/// The files have been renamed at this point, so failing to record them is
+/// only worth a warning.
+fn record_operation(
+    replacements: &[Rename],
+    command: String,
+    index: Option<IndexMoves>,
+    stashed: Vec<Rename>,
+) {
+    if let Err(e) = history::record_operation(replacements, command, index, stashed) {
+        eprintln!("Warning: could not record the renames for undo: {:#}", e);
+    }
+}
+
+/// The command line as recorded in the history.
+fn command_line(args: &[OsString]) -> String {
+    shell_words::join(args.iter().map(|a| a.to_string_lossy()))
+}
+
+fn print_history(history: &History, scope: Option<&Path>) {
+    let operations: Vec<_> = history.operations(scope).collect();
+    if operations.is_empty() {
+        println!("No renaming operations recorded.");
+        return;
+    }
+
+    for op in operations {
+        let line = format!(
+            "{:>4}  {}  {:>5} files  {}  {}",
+            op.id,
+            humantime::format_rfc3339_seconds(op.time()),
+            op.renames.len(),
+            op.cwd.display(),
+            op.command
+        );
+        if op.undone {
+            println!("{} (undone)", Colour::Fixed(8).paint(line));
+        } else {
+            println!("{}", line);
         }
     }
-    fs::remove_file(backup_file)?;
-    Ok(undo_replacements)
 }
 
 fn main() -> anyhow::Result<()> {
-    let opts = Opts::parse_from(wild::args());
-    let backup_file = std::env::temp_dir().join("pipe-renamer_undo.json");
+    let args: Vec<OsString> = wild::args_os().collect();
+    let opts = Opts::parse_from(&args);
+    if opts.git && opts.backend == BackendKind::Git {
+        bail!("--git cannot be used with --backend git, which moves the index entries itself.");
+    }
+    let cwd = env::current_dir()?;
+    let scope = if opts.any_dir {
+        None
+    } else {
+        Some(cwd.as_path())
+    };
+
+    if opts.history {
+        print_history(&History::load(&state_dir()?)?, scope);
+        return Ok(());
+    }
+
+    let events = Events::new(opts.output);
+    // Keep stdout clean for the list of new file names, the plan or events.
+    let mut out: Box<dyn Write> = if events.enabled() {
+        Box::new(io::sink())
+    } else if opts.print0 || opts.format.is_some() {
+        Box::new(io::stderr())
+    } else {
+        Box::new(io::stdout())
+    };
+
+    let result = run(&opts, &args, scope, &events, out.as_mut());
+    if let Err(e) = &result {
+        events.emit_failure(e)?;
+    }
+    result
+}
+
+/// Undo, redo, apply a plan or edit the file names, reporting to `events` and
+/// `out`.
+fn run(
+    opts: &Opts,
+    args: &[OsString],
+    scope: Option<&Path>,
+    events: &Events,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    if let Some(id) = opts.undo {
+        let mut history = History::load(&state_dir()?)?;
+        let op = history.undo_candidate(id, scope)?;
+        let (id, replacements) = (op.id, op.undo_renames());
+        let index = op.git.as_ref().map(IndexMoves::reversed);
+        let stashed = op.trash.clone();
+        check_restorable(&replacements, "Undo")?;
+        check_restorable(&restore_moves(&stashed), "Undo")?;
+        if !run_renames(
+            &Batch {
+                renames: &replacements,
+                parents: false,
+                index: index.as_ref(),
+                trash: TrashMoves::Restore(&stashed),
+            },
+            opts,
+            events,
+            out,
+        )? {
+            return Ok(());
+        }
+        history.mark_undone(id);
+        history.save()?;
+        writeln!(
+            out,
+            "Restored {} files.",
+            replacements.len() + stashed.len()
+        )?;
+        return Ok(());
+    }
 
-    if opts.undo {
-        let replacements = load_undo_renames(backup_file)?;
-        execute_renames(&replacements, opts.rename_command, false)?;
-        println!("Restored {} files.", replacements.len());
+    if opts.redo {
+        let mut history = History::load(&state_dir()?)?;
+        let op = history.redo_candidate(scope)?;
+        let (id, replacements) = (op.id, op.renames.clone());
+        let index = op.git.clone();
+        let stashed = op.trash.clone();
+        check_restorable(&replacements, "Redo")?;
+        check_restorable(&stashed, "Redo")?;
+        if !run_renames(
+            &Batch {
+                renames: &replacements,
+                parents: false,
+                index: index.as_ref(),
+                trash: TrashMoves::Stash(&stashed),
+            },
+            opts,
+            events,
+            out,
+        )? {
+            return Ok(());
+        }
+        history.mark_redone(id);
+        history.save()?;
+        writeln!(out, "Renamed {} files.", replacements.len())?;
         return Ok(());
     }
 
-    let input_files = get_input_files(opts.files)?;
-    check_input_files(&input_files)?;
+    if let Some(plan) = &opts.apply {
+        let plan = read_plan(plan)?;
+        let (originals, news): (Vec<_>, Vec<_>) =
+            plan.into_iter().map(|r| (r.original, r.new)).unzip();
+        check_input_files(&originals, out)?;
+        let found = find_renames(&originals, &news)?;
+        let replacements = resolve_with_policy(found, &[], opts, events)?;
+        events.emit(&Event::Plan {
+            renames: &replacements,
+            deleted: &[],
+        })?;
+        let force = opts.force || opts.on_conflict.is_some();
+        check_for_existing_files(&replacements, &[], force, out, events)?;
+        if opts.dry_run {
+            return print_dry_run(&replacements, &[], opts, out);
+        }
+
+        if !opts.quiet {
+            print_replacements(&replacements, &[], opts.pretty_diff, out)?;
+        }
+        if let MenuItem::No = prompt(&[MenuItem::Yes, MenuItem::No], opts.assume_yes)? {
+            writeln!(out, "Aborting.")?;
+            return Ok(());
+        }
+        let index = find_index_moves(&replacements, opts.git)?;
+        let stashed = trash::trash_moves(&replacements, &[], &mut print_warning)?;
+        if !run_renames(
+            &Batch {
+                renames: &replacements,
+                parents: opts.parents,
+                index: index.as_ref(),
+                trash: TrashMoves::Stash(&stashed),
+            },
+            opts,
+            events,
+            out,
+        )? {
+            return Ok(());
+        }
+        record_operation(&replacements, command_line(args), index, stashed);
+        if opts.print0 {
+            print_new_files(&resolved_names(&originals, &replacements))?;
+        }
+        return Ok(());
+    }
+
+    let input_files = get_input_files(
+        opts.files.clone(),
+        opts.null,
+        &opts.walk,
+        opts.sort,
+        opts.reverse,
+    )?;
+    check_input_files(&input_files, out)?;
 
     let editor = {
         let default_editor = if cfg!(windows) { "notepad.exe" } else { "vim" };
         opts.editor
+            .clone()
             .unwrap_or_else(|| env::var("EDITOR").unwrap_or(default_editor.to_string()))
     };
 
-    let mut buffer = input_files.clone();
+    // Names that are not valid UTF-8 can only be edited with escapes.
+    let escape = match opts.escape {
+        None if input_files.iter().any(|f| f.to_str().is_none()) => {
+            writeln!(
+                out,
+                "Some file names are not valid UTF-8, escaping them with `--escape c`."
+            )?;
+            Some(Escape::C)
+        }
+        // Names read with --null may contain line breaks, which would split
+        // them across lines.
+        None if opts.null && input_files.iter().any(|f| has_line_break(f)) => {
+            writeln!(
+                out,
+                "Some file names contain line breaks, escaping them with `--escape c`."
+            )?;
+            Some(Escape::C)
+        }
+        escape => escape,
+    };
+
+    // The name of every input file, or `None` once its line is deleted.
+    let originals: Vec<Option<PathBuf>> = input_files.iter().cloned().map(Some).collect();
+    let mut buffer = originals.clone();
+    // Substitutions and filters replace the first round of editing, Edit and
+    // Reset still open the editor.
+    let mut substitutions = Some(opts.regex.clone()).filter(|subs| !subs.is_empty());
+    let mut filter = opts.filter.clone();
+    let line_ids = opts.line_ids || opts.allow_delete;
 
     loop {
-        let new_files = open_editor(&buffer, &editor, opts.filenames_only)?;
-        let replacements = find_renames(&input_files, &new_files)?;
-        println!();
+        let edited = if let Some(subs) = substitutions.take() {
+            substitute_names(&buffer, &subs, opts.filenames_only)
+        } else if let Some(command) = filter.take() {
+            run_filter(&buffer, &command, opts.filenames_only, escape, line_ids)?
+        } else {
+            open_editor(&buffer, &editor, opts.filenames_only, escape, line_ids)?
+        };
+        if edited.len() != input_files.len() {
+            return Err(RenamerError::UnequalLines.into());
+        }
+        let (kept, new_files): (Vec<PathBuf>, Vec<PathBuf>) = zip(&input_files, &edited)
+            .filter_map(|(file, new)| Some((file.clone(), new.clone()?)))
+            .unzip();
+        let deleted: Vec<PathBuf> = zip(&input_files, &edited)
+            .filter(|(_, new)| new.is_none())
+            .map(|(file, _)| file.clone())
+            .collect();
+        if !deleted.is_empty() && !opts.allow_delete {
+            bail!(
+                "The lines of {} files are missing, pass --allow-delete to delete them.",
+                deleted.len()
+            );
+        }
 
-        let check_existing = check_for_existing_files(&replacements, opts.force);
+        let found = match find_renames(&kept, &new_files) {
+            Err(RenamerError::NoReplacementsFound) if !deleted.is_empty() => vec![],
+            found => found?,
+        };
+        let replacements = resolve_with_policy(found, &deleted, opts, events)?;
+        events.emit(&Event::Plan {
+            renames: &replacements,
+            deleted: &deleted,
+        })?;
+        writeln!(out)?;
+
+        // Conflicts are either resolved by now or chosen to overwrite.
+        let force = opts.force || opts.on_conflict.is_some();
+        let check_existing = check_for_existing_files(&replacements, &deleted, force, out, events);
+
+        if opts.dry_run {
+            check_existing?;
+            return print_dry_run(&replacements, &deleted, opts, out);
+        }
 
         let menu_options = match check_existing {
             Ok(()) => {
                 if !opts.quiet {
-                    print_replacements(&replacements, opts.pretty_diff);
+                    print_replacements(&replacements, &deleted, opts.pretty_diff, out)?;
                 }
                 vec![MenuItem::Yes, MenuItem::No, MenuItem::Edit, MenuItem::Reset]
             }
@@ -549,17 +1119,39 @@ fn main() -> anyhow::Result<()> {
         };
 
         match prompt(&menu_options, opts.assume_yes)? {
+            // Bring back the deleted lines, keeping the other edits.
+            MenuItem::Yes if !confirm_deletions(&deleted, opts.assume_yes)? => {
+                buffer = zip(edited, &originals)
+                    .map(|(new, original)| new.or_else(|| original.clone()))
+                    .collect();
+            }
             MenuItem::Yes => {
-                execute_renames(&replacements, opts.rename_command, opts.parents)?;
-                write_undo_renames(backup_file, replacements)?;
+                let index = find_index_moves(&replacements, opts.git)?;
+                let stashed = trash::trash_moves(&replacements, &deleted, &mut print_warning)?;
+                if run_renames(
+                    &Batch {
+                        renames: &replacements,
+                        parents: opts.parents,
+                        index: index.as_ref(),
+                        trash: TrashMoves::Stash(&stashed),
+                    },
+                    opts,
+                    events,
+                    out,
+                )? {
+                    record_operation(&replacements, command_line(args), index, stashed);
+                    if opts.print0 {
+                        print_new_files(&resolved_names(&kept, &replacements))?;
+                    }
+                }
                 break;
             }
             MenuItem::No => {
-                println!("Aborting.");
+                writeln!(out, "Aborting.")?;
                 break;
             }
-            MenuItem::Edit => buffer = new_files.clone(),
-            MenuItem::Reset => buffer = input_files.clone(),
+            MenuItem::Edit => buffer = edited,
+            MenuItem::Reset => buffer = originals.clone(),
         }
     }
 
@@ -0,0 +1,129 @@
+//! Structured events for integrations.
+
+use std::cell::Cell;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Serialize, Serializer};
+
+use crate::{path_serde, Rename};
+
+/// How progress and results are reported.
+#[cfg_attr(feature = "cli", derive(clap::ArgEnum))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputMode {
+    /// Human-readable text
+    Text,
+    /// Newline-delimited JSON events on stdout
+    Json,
+}
+
+/// An event reported with `--output json`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event<'a> {
+    /// The renames found after editing, and the files to delete.
+    Plan {
+        renames: &'a [Rename],
+        #[serde(serialize_with = "serialize_paths")]
+        deleted: &'a [PathBuf],
+    },
+    /// A rename that would overwrite an existing file.
+    Conflict(&'a Rename),
+    /// A rename that has been executed.
+    RenameDone(&'a Rename),
+    /// A rename that failed, after which the completed ones are rolled back.
+    RenameFailed {
+        #[serde(flatten)]
+        rename: &'a Rename,
+        error: String,
+    },
+    /// The outcome of the whole operation.
+    Summary {
+        renamed: usize,
+        error: Option<String>,
+    },
+}
+
+fn serialize_paths<S: Serializer>(paths: &&[PathBuf], serializer: S) -> Result<S::Ok, S::Error> {
+    struct Item<'a>(&'a Path);
+    impl Serialize for Item<'_> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            path_serde::serialize(self.0, serializer)
+        }
+    }
+    serializer.collect_seq(paths.iter().map(|path| Item(path)))
+}
+
+/// Writes events to stdout if JSON output is enabled, which it is not by
+/// default.
+#[derive(Debug, Clone, Default)]
+pub struct Events {
+    enabled: bool,
+    summarized: Cell<bool>,
+}
+
+impl Events {
+    pub fn new(mode: OutputMode) -> Self {
+        Events {
+            enabled: mode == OutputMode::Json,
+            summarized: Cell::new(false),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn emit(&self, event: &Event) -> io::Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+        if let Event::Summary { .. } = event {
+            self.summarized.set(true);
+        }
+        let mut stdout = io::stdout().lock();
+        serde_json::to_writer(&mut stdout, event)?;
+        writeln!(stdout)?;
+        stdout.flush()
+    }
+
+    /// Report a failure as the outcome, unless a summary has been emitted
+    /// already, so every run ends with a summary.
+    pub fn emit_failure(&self, error: &anyhow::Error) -> io::Result<()> {
+        if self.summarized.get() {
+            return Ok(());
+        }
+        self.emit(&Event::Summary {
+            renamed: 0,
+            error: Some(format!("{:#}", error)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_shape() {
+        let rename = Rename {
+            original: "a".into(),
+            new: "b".into(),
+        };
+        fn json(event: &Event) -> serde_json::Value {
+            serde_json::to_value(event).unwrap()
+        }
+        assert_eq!(
+            json(&Event::RenameDone(&rename)),
+            serde_json::json!({"event": "rename-done", "original": "a", "new": "b"})
+        );
+        assert_eq!(
+            json(&Event::RenameFailed {
+                rename: &rename,
+                error: "oops".into()
+            }),
+            serde_json::json!({"event": "rename-failed", "original": "a", "new": "b", "error": "oops"})
+        );
+    }
+}
@@ -0,0 +1,517 @@
+//! Normalizing, validating and ordering renames.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use crate::{Rename, RenamerError};
+
+/// Rewrite renames that are affected by renaming one of their ancestor
+/// directories in the same batch.
+///
+/// A new path inside a directory that is moved away, and not replaced by
+/// anything, follows the directory: with `dir -> dir2`, the rename
+/// `dir/a -> dir/b` becomes `dir/a -> dir2/b`. Renames that are already taken
+/// care of by moving their directory, like `dir/a -> dir2/a`, are dropped.
+pub fn rebase_nested(renames: Vec<Rename>) -> Result<Vec<Rename>, RenamerError> {
+    let originals: HashMap<&Path, usize> = renames
+        .iter()
+        .enumerate()
+        .map(|(i, r)| (r.original.as_path(), i))
+        .collect();
+    let reoccupied: HashSet<&Path> = renames.iter().map(|r| r.new.as_path()).collect();
+    let mut news: Vec<PathBuf> = renames.iter().map(|r| r.new.clone()).collect();
+
+    // Every round resolves one more level of nesting, so this only goes on
+    // if directories are moved into each other.
+    let mut changed = true;
+    let mut rounds = 0;
+    while changed {
+        if rounds > renames.len() {
+            return Err(RenamerError::NestedCycle);
+        }
+        rounds += 1;
+        changed = false;
+
+        for i in 0..news.len() {
+            let moved_dir = news[i]
+                .ancestors()
+                .skip(1)
+                .filter(|dir| !reoccupied.contains(dir))
+                .find_map(|dir| Some((dir, *originals.get(dir)?)))
+                .filter(|&(_, j)| j != i);
+            if let Some((dir, j)) = moved_dir {
+                let rel = news[i].strip_prefix(dir).expect("ancestor is a prefix");
+                news[i] = news[j].join(rel);
+                changed = true;
+            }
+        }
+    }
+
+    // Where each entry ends up by only moving its closest renamed ancestor.
+    let carried: Vec<Option<PathBuf>> = renames
+        .iter()
+        .map(|r| {
+            r.original.ancestors().skip(1).find_map(|dir| {
+                let &j = originals.get(dir)?;
+                Some(news[j].join(r.original.strip_prefix(dir).ok()?))
+            })
+        })
+        .collect();
+
+    Ok(renames
+        .iter()
+        .zip(news)
+        .zip(carried)
+        .filter(|((_, new), carried)| carried.as_ref() != Some(new))
+        .map(|((rename, new), _)| Rename {
+            original: rename.original.clone(),
+            new,
+        })
+        .collect())
+}
+
+/// A rename onto a file that stays in place.
+#[derive(Debug)]
+pub struct Conflict<'a> {
+    pub rename: &'a Rename,
+    /// Where the file in the way is now, before any renames.
+    pub existing: PathBuf,
+}
+
+/// Find the renames whose new path is taken by a file that stays in place.
+///
+/// Targets inside a renamed directory are looked up below the directory's
+/// current path.
+pub fn find_conflicts(renames: &[Rename]) -> Vec<Conflict<'_>> {
+    let occupancy = Occupancy::new(renames);
+    renames
+        .iter()
+        .filter_map(|rename| {
+            let existing = occupancy.existing(&rename.new)?;
+            if same_file(&existing, &rename.original) {
+                return None;
+            }
+            Some(Conflict { rename, existing })
+        })
+        .collect()
+}
+
+/// The conflicts that stop the renames, leaving out files that are deleted
+/// in the same batch since they are out of the way.
+pub fn blocking_conflicts<'a>(renames: &'a [Rename], deleted: &[PathBuf]) -> Vec<Conflict<'a>> {
+    find_conflicts(renames)
+        .into_iter()
+        .filter(|conflict| !deleted.contains(&conflict.existing))
+        .collect()
+}
+
+/// Check that the files to rename exist and are listed only once.
+pub fn check_input_files(files: &[PathBuf]) -> Result<(), RenamerError> {
+    let nonexistent: Vec<PathBuf> = files
+        .iter()
+        .filter(|file| !file.exists())
+        .cloned()
+        .collect();
+    if !nonexistent.is_empty() {
+        return Err(RenamerError::NonexistentFiles(nonexistent));
+    }
+
+    let mut set = HashSet::new();
+    if !files.iter().all(|file| set.insert(file)) {
+        return Err(RenamerError::DuplicateInput);
+    }
+    Ok(())
+}
+
+/// Which paths are still taken by other files once a batch of renames is done.
+pub(crate) struct Occupancy<'a> {
+    vacated: HashSet<&'a Path>,
+    news: HashMap<&'a Path, &'a Rename>,
+}
+
+impl<'a> Occupancy<'a> {
+    pub(crate) fn new(renames: &'a [Rename]) -> Self {
+        Occupancy {
+            // Files that are renamed themselves will be out of the way in time.
+            vacated: renames.iter().map(|r| r.original.as_path()).collect(),
+            news: renames.iter().map(|r| (r.new.as_path(), r)).collect(),
+        }
+    }
+
+    /// The current path of the file that stays at `path` after the renames,
+    /// if there is one.
+    pub(crate) fn existing(&self, path: &Path) -> Option<PathBuf> {
+        let current = path
+            .ancestors()
+            .skip(1)
+            .find_map(|dir| {
+                let moved = self.news.get(dir)?;
+                Some(moved.original.join(path.strip_prefix(dir).ok()?))
+            })
+            .unwrap_or_else(|| path.to_path_buf());
+        if self.vacated.contains(current.as_path()) || current.symlink_metadata().is_err() {
+            return None;
+        }
+        Some(current)
+    }
+}
+
+/// Whether two paths differing only in case refer to the same file, as they
+/// do on case-insensitive file systems.
+fn same_file(a: &Path, b: &Path) -> bool {
+    let lowercase = |p: &Path| p.to_string_lossy().to_lowercase();
+    if a == b || lowercase(a) != lowercase(b) {
+        return false;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        match (a.symlink_metadata(), b.symlink_metadata()) {
+            (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+            _ => false,
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        match (a.canonicalize(), b.canonicalize()) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// Order renames so that no step overwrites a file that is still waiting to be
+/// renamed itself.
+///
+/// Renames form chains (`a -> b`, `b -> c`) and cycles (`a -> b`, `b -> a`)
+/// whenever a new name is the original name of another rename. Chains are
+/// executed from their free end, and cycles are broken by moving one of their
+/// files to a unique temporary name first.
+///
+/// Directories are renamed before the entries inside them, which are then
+/// renamed from their new location, and before anything is moved into them.
+///
+/// Case-only renames on case-insensitive file systems go through a temporary
+/// name, as renaming directly may do nothing.
+pub fn plan_renames(renames: &[Rename]) -> Vec<Rename> {
+    Planner::new(renames).plan()
+}
+
+/// Why a rename has to wait for another one.
+enum Blocker {
+    /// The new path is the current path of the other rename.
+    Target(usize),
+    /// The other rename moves a directory containing the current or new path.
+    Directory(usize),
+}
+
+struct Planner<'a> {
+    renames: &'a [Rename],
+    /// Current path of each entry, which changes when it or its directory moves.
+    sources: Vec<PathBuf>,
+    /// Current paths of the pending renames.
+    occupied: HashMap<PathBuf, usize>,
+    /// New paths of the pending renames.
+    targets: HashMap<PathBuf, usize>,
+    reserved: HashSet<PathBuf>,
+    has_descendants: Vec<bool>,
+    /// Whether the new path only differs in case and already refers to the
+    /// same file.
+    same_file: Vec<bool>,
+    done: Vec<bool>,
+    waiting: HashMap<usize, Vec<usize>>,
+    queue: VecDeque<usize>,
+    steps: Vec<Rename>,
+}
+
+impl<'a> Planner<'a> {
+    fn new(renames: &'a [Rename]) -> Self {
+        let sources: Vec<PathBuf> = renames.iter().map(|r| r.original.clone()).collect();
+        let occupied: HashMap<PathBuf, usize> = sources
+            .iter()
+            .enumerate()
+            .map(|(i, source)| (source.clone(), i))
+            .collect();
+        let mut has_descendants = vec![false; renames.len()];
+        for source in &sources {
+            for dir in source.ancestors().skip(1) {
+                if let Some(&j) = occupied.get(dir) {
+                    has_descendants[j] = true;
+                }
+            }
+        }
+
+        Planner {
+            renames,
+            targets: renames
+                .iter()
+                .enumerate()
+                .map(|(i, r)| (r.new.clone(), i))
+                .collect(),
+            reserved: renames
+                .iter()
+                .flat_map(|r| [r.original.clone(), r.new.clone()])
+                .collect(),
+            sources,
+            occupied,
+            has_descendants,
+            same_file: renames
+                .iter()
+                .map(|r| same_file(&r.original, &r.new))
+                .collect(),
+            done: vec![false; renames.len()],
+            waiting: HashMap::new(),
+            queue: (0..renames.len()).collect(),
+            steps: Vec::with_capacity(renames.len()),
+        }
+    }
+
+    fn plan(mut self) -> Vec<Rename> {
+        let mut cursor = 0;
+        loop {
+            while let Some(i) = self.queue.pop_front() {
+                if self.done[i] {
+                    continue;
+                }
+                match self.blocker(i) {
+                    // Retry once the other rename has been executed.
+                    Some(Blocker::Target(j)) | Some(Blocker::Directory(j)) => {
+                        self.waiting.entry(j).or_default().push(i)
+                    }
+                    None => self.execute(i),
+                }
+            }
+
+            // Everything left is part of a cycle.
+            while cursor < self.renames.len() && self.done[cursor] {
+                cursor += 1;
+            }
+            if cursor == self.renames.len() {
+                break;
+            }
+
+            let occupant = (cursor..self.renames.len())
+                .filter(|&i| !self.done[i])
+                .find_map(|i| match self.blocker(i) {
+                    Some(Blocker::Target(j)) => Some(j),
+                    _ => None,
+                });
+            match occupant {
+                Some(j) => self.park(j),
+                // Only directories waiting on each other are left, which the
+                // file system will most likely refuse.
+                None => self.execute(cursor),
+            }
+        }
+
+        self.steps
+    }
+
+    fn blocker(&self, i: usize) -> Option<Blocker> {
+        let new = &self.renames[i].new;
+        if let Some(&j) = self.occupied.get(new) {
+            return Some(Blocker::Target(j));
+        }
+        let in_moved_dir = self.sources[i]
+            .ancestors()
+            .skip(1)
+            .find_map(|dir| self.occupied.get(dir));
+        let into_moved_dir = new
+            .ancestors()
+            .skip(1)
+            .filter_map(|dir| self.targets.get(dir))
+            .find(|&&j| j != i);
+        in_moved_dir
+            .or(into_moved_dir)
+            .map(|&j| Blocker::Directory(j))
+    }
+
+    fn execute(&mut self, i: usize) {
+        if self.same_file[i] {
+            self.park(i);
+        }
+        self.done[i] = true;
+        self.targets.remove(&self.renames[i].new);
+        self.move_source(i, self.renames[i].new.clone());
+    }
+
+    /// Move an entry out of the way to a temporary name.
+    fn park(&mut self, i: usize) {
+        let temp = temp_name(&self.sources[i], &self.reserved);
+        self.reserved.insert(temp.clone());
+        self.move_source(i, temp);
+    }
+
+    /// Move an entry along with everything below it, and wake up the renames
+    /// waiting for it.
+    fn move_source(&mut self, i: usize, to: PathBuf) {
+        let from = std::mem::replace(&mut self.sources[i], to.clone());
+        self.occupied.remove(&from);
+        if !self.done[i] {
+            self.occupied.insert(to.clone(), i);
+        }
+
+        if self.has_descendants[i] {
+            for k in 0..self.sources.len() {
+                if self.done[k] || k == i {
+                    continue;
+                }
+                if let Ok(rel) = self.sources[k].strip_prefix(&from) {
+                    let rebased = to.join(rel);
+                    self.occupied.remove(&self.sources[k]);
+                    self.occupied.insert(rebased.clone(), k);
+                    self.sources[k] = rebased;
+                }
+            }
+        }
+
+        // Entries can end up in place by moving their directory.
+        if from != to {
+            self.steps.push(Rename {
+                original: from,
+                new: to,
+            });
+        }
+        let waiting = self.waiting.remove(&i).unwrap_or_default();
+        self.queue.extend(waiting);
+    }
+}
+
+/// Find an unused name next to `path` to park it on while breaking a cycle.
+fn temp_name(path: &Path, reserved: &HashSet<PathBuf>) -> PathBuf {
+    let file_name = path.file_name().unwrap_or_default();
+    (0..)
+        .map(|n| {
+            let mut temp = OsString::from(".");
+            temp.push(file_name);
+            temp.push(format!(".renamer-{}-{}", std::process::id(), n));
+            path.with_file_name(temp)
+        })
+        .find(|p| !reserved.contains(p) && p.symlink_metadata().is_err())
+        .expect("exhausted temporary file names")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn rename(original: &str, new: &str) -> Rename {
+        Rename {
+            original: original.into(),
+            new: new.into(),
+        }
+    }
+
+    /// Replay the steps on a set of paths with their contents, moving
+    /// everything below a path along with it.
+    fn replay<'a>(files: &[(&str, &'a str)], steps: &[Rename]) -> HashMap<PathBuf, &'a str> {
+        let mut names: HashMap<PathBuf, &str> = files
+            .iter()
+            .map(|&(name, content)| (PathBuf::from(name), content))
+            .collect();
+        for step in steps {
+            assert!(!names.contains_key(&step.new), "{} overwrites", step);
+            let moved: Vec<_> = names
+                .keys()
+                .filter(|p| p.starts_with(&step.original))
+                .cloned()
+                .collect();
+            assert!(!moved.is_empty(), "{} is missing", step.original.display());
+            for path in moved {
+                let content = names.remove(&path).unwrap();
+                let rel = path.strip_prefix(&step.original).unwrap();
+                names.insert(step.new.join(rel), content);
+            }
+        }
+        names
+    }
+
+    #[test]
+    fn chains_run_from_free_end() {
+        let steps = plan_renames(&[rename("1", "2"), rename("2", "3")]);
+        assert_eq!(
+            steps.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            ["2 -> 3", "1 -> 2"]
+        );
+    }
+
+    #[test]
+    fn cycles_go_through_temp_name() {
+        let steps = plan_renames(&[rename("a", "b"), rename("b", "c"), rename("c", "a")]);
+        assert_eq!(steps.len(), 4);
+
+        let names = replay(&[("a", "A"), ("b", "B"), ("c", "C")], &steps);
+        assert_eq!(names[Path::new("b")], "A");
+        assert_eq!(names[Path::new("c")], "B");
+        assert_eq!(names[Path::new("a")], "C");
+    }
+
+    #[test]
+    fn case_only_renames_go_through_temp_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a"), dir.path().join("A"));
+        fs::write(&a, "").unwrap();
+        // A hard link stands in for a case-insensitive file system.
+        fs::hard_link(&a, &b).unwrap();
+
+        let renames = [Rename {
+            original: a.clone(),
+            new: b.clone(),
+        }];
+        assert!(find_conflicts(&renames).is_empty());
+        let steps = plan_renames(&renames);
+        assert_eq!(steps.len(), 2);
+        assert_eq!((&steps[0].original, &steps[1].new), (&a, &b));
+    }
+
+    #[test]
+    fn nested_renames_are_rebased() {
+        let renames = rebase_nested(vec![
+            rename("dir/a", "dir/b"),
+            rename("dir", "new"),
+            rename("dir/c", "new/c"),
+            rename("x", "dir/x"),
+        ])
+        .unwrap();
+        assert_eq!(
+            renames.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            ["dir/a -> new/b", "dir -> new", "x -> new/x"]
+        );
+
+        let files = [("dir", "D"), ("dir/a", "A"), ("dir/c", "C"), ("x", "X")];
+        let names = replay(&files, &plan_renames(&renames));
+        assert_eq!(names[Path::new("new")], "D");
+        assert_eq!(names[Path::new("new/b")], "A");
+        assert_eq!(names[Path::new("new/c")], "C");
+        assert_eq!(names[Path::new("new/x")], "X");
+    }
+
+    #[test]
+    fn swapped_directories_keep_new_paths() {
+        let renames = rebase_nested(vec![
+            rename("a", "b"),
+            rename("b", "a"),
+            rename("a/x", "b/y"),
+        ])
+        .unwrap();
+        assert_eq!(renames.len(), 3);
+
+        let files = [("a", "A"), ("a/x", "X"), ("b", "B")];
+        let names = replay(&files, &plan_renames(&renames));
+        assert_eq!(names[Path::new("b")], "A");
+        assert_eq!(names[Path::new("b/y")], "X");
+        assert_eq!(names[Path::new("a")], "B");
+    }
+
+    #[test]
+    fn directories_moved_into_each_other() {
+        let renames = vec![rename("a", "b/a"), rename("b", "a/b")];
+        assert!(matches!(
+            rebase_nested(renames),
+            Err(RenamerError::NestedCycle)
+        ));
+    }
+}
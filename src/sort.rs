@@ -0,0 +1,135 @@
+//! Ordering file names before editing.
+
+use std::cmp::Ordering;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::escape::os_str_bytes;
+
+/// Order of the file names in the editor buffer.
+#[cfg_attr(feature = "cli", derive(clap::ArgEnum))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortKey {
+    /// By path, component-wise
+    Name,
+    /// By path, comparing runs of digits by their value (file2 < file10)
+    Natural,
+    /// By modification time, oldest first
+    Mtime,
+    /// By size, smallest first
+    Size,
+    /// By extension, then naturally by path
+    Ext,
+}
+
+/// Sort file names in place.
+pub fn sort_files(files: &mut [PathBuf], key: SortKey) {
+    match key {
+        SortKey::Name => files.sort(),
+        SortKey::Natural => files.sort_by(|a, b| natural_cmp(a, b)),
+        SortKey::Mtime => files.sort_by_cached_key(|f| {
+            metadata(f)
+                .and_then(|m| m.modified().ok())
+                .unwrap_or(SystemTime::UNIX_EPOCH)
+        }),
+        SortKey::Size => files.sort_by_cached_key(|f| metadata(f).map_or(0, |m| m.len())),
+        SortKey::Ext => files.sort_by(|a, b| {
+            let ext = |p: &Path| p.extension().map(|e| os_str_bytes(e).into_owned());
+            ext(a).cmp(&ext(b)).then_with(|| natural_cmp(a, b))
+        }),
+    }
+}
+
+fn metadata(path: &Path) -> Option<fs::Metadata> {
+    fs::symlink_metadata(path).ok()
+}
+
+/// Compare paths so that runs of digits are ordered by their numeric value.
+///
+/// Paths are compared component by component, so directories are followed by
+/// their contents.
+pub fn natural_cmp(a: &Path, b: &Path) -> Ordering {
+    let (mut a, mut b) = (a.components(), b.components());
+    loop {
+        match (a.next(), b.next()) {
+            (Some(x), Some(y)) => {
+                let (x, y) = (os_str_bytes(x.as_os_str()), os_str_bytes(y.as_os_str()));
+                match natural_cmp_bytes(&x, &y) {
+                    Ordering::Equal => continue,
+                    ordering => return ordering,
+                }
+            }
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+        }
+    }
+}
+
+fn natural_cmp_bytes(mut a: &[u8], mut b: &[u8]) -> Ordering {
+    while !a.is_empty() && !b.is_empty() {
+        if !a[0].is_ascii_digit() || !b[0].is_ascii_digit() {
+            match a[0].cmp(&b[0]) {
+                Ordering::Equal => {
+                    a = &a[1..];
+                    b = &b[1..];
+                    continue;
+                }
+                ordering => return ordering,
+            }
+        }
+
+        let (a_run, b_run) = (digit_run(a), digit_run(b));
+        let (a_num, b_num) = (trim_zeros(a_run), trim_zeros(b_run));
+        let ordering = a_num
+            .len()
+            .cmp(&b_num.len())
+            .then_with(|| a_num.cmp(b_num))
+            // Fewer leading zeros first, so the order is total.
+            .then_with(|| a_run.len().cmp(&b_run.len()));
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+
+        a = &a[a_run.len()..];
+        b = &b[b_run.len()..];
+    }
+    a.len().cmp(&b.len())
+}
+
+fn digit_run(s: &[u8]) -> &[u8] {
+    let len = s.iter().take_while(|b| b.is_ascii_digit()).count();
+    &s[..len]
+}
+
+fn trim_zeros(digits: &[u8]) -> &[u8] {
+    let zeros = digits.iter().take_while(|&&d| d == b'0').count();
+    &digits[zeros..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn natural_order() {
+        let mut files: Vec<PathBuf> = [
+            "file10", "file2", "file02", "file1b", "file", "a/9", "a10", "a-b", "a.txt", "a/x", "a",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+        sort_files(&mut files, SortKey::Natural);
+        assert_eq!(
+            files,
+            [
+                "a", "a/9", "a/x", "a-b", "a.txt", "a10", "file", "file1b", "file2", "file02",
+                "file10"
+            ]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,162 @@
+//! Sed-like substitutions on file names.
+
+use std::ffi::{OsStr, OsString};
+use std::str::FromStr;
+
+use regex::bytes::{Regex, RegexBuilder};
+
+use crate::escape::{os_str_bytes, os_string_from_bytes};
+
+/// A sed-like substitution, `s/PATTERN/REPLACEMENT/FLAGS`.
+///
+/// Any character that is not alphanumeric or a backslash can stand in for
+/// `/`, and is escaped with a backslash inside the pattern and replacement.
+/// The replacement uses the syntax of the `regex` crate rather than sed's:
+/// capture groups are `$1`, `${name}` or `$0` for the whole match, and `$$` is
+/// a literal `$`. Sed's `\1` is accepted as well, but `&` and `\n` have no
+/// special meaning. The flags are `g` to replace all matches instead of the
+/// first one and `i` to match case-insensitively.
+#[derive(Debug, Clone)]
+pub struct Substitution {
+    regex: Regex,
+    replacement: Vec<u8>,
+    global: bool,
+}
+
+impl Substitution {
+    /// Apply the substitution to a single name.
+    pub fn apply(&self, name: &OsStr) -> OsString {
+        let name = os_str_bytes(name);
+        let replaced = if self.global {
+            self.regex.replace_all(&name, &self.replacement[..])
+        } else {
+            self.regex.replace(&name, &self.replacement[..])
+        };
+        os_string_from_bytes(replaced.into_owned())
+    }
+}
+
+/// Apply the substitutions to a name one after the other.
+pub fn substitute(substitutions: &[Substitution], name: &OsStr) -> OsString {
+    substitutions
+        .iter()
+        .fold(name.to_os_string(), |name, sub| sub.apply(&name))
+}
+
+impl FromStr for Substitution {
+    type Err = String;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let mut chars = expression.chars();
+        let delimiter = match (chars.next(), chars.next()) {
+            (Some('s'), Some(d)) if !d.is_alphanumeric() && d != '\\' => d,
+            _ => return Err("expected an expression like s/PATTERN/REPLACEMENT/FLAGS".into()),
+        };
+
+        let parts = split_unescaped(chars.as_str(), delimiter);
+        let (pattern, replacement, flags) = match &parts[..] {
+            [pattern, replacement, flags] => (pattern, replacement, flags),
+            [_, _] => return Err(format!("missing closing {:?}", delimiter)),
+            _ => return Err(format!("expected 3 parts separated by {:?}", delimiter)),
+        };
+
+        let mut builder = RegexBuilder::new(pattern);
+        let mut global = false;
+        for flag in flags.chars() {
+            match flag {
+                'g' => global = true,
+                'i' => {
+                    builder.case_insensitive(true);
+                }
+                c => return Err(format!("unknown flag {:?}", c)),
+            }
+        }
+
+        Ok(Substitution {
+            regex: builder.build().map_err(|e| e.to_string())?,
+            replacement: sed_replacement(replacement).into_bytes(),
+            global,
+        })
+    }
+}
+
+/// Split on `delimiter` unless it is escaped, removing the escaping
+/// backslash. Other escapes are kept as they are.
+fn split_unescaped(s: &str, delimiter: char) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        let part = parts.last_mut().expect("parts is never empty");
+        match c {
+            '\\' => match chars.next() {
+                Some(d) if d == delimiter => part.push(d),
+                Some(d) => {
+                    part.push('\\');
+                    part.push(d);
+                }
+                None => part.push('\\'),
+            },
+            c if c == delimiter => parts.push(String::new()),
+            c => part.push(c),
+        }
+    }
+    parts
+}
+
+/// Turn sed-style group references like `\1` into `${1}`.
+fn sed_replacement(replacement: &str) -> String {
+    let mut converted = String::with_capacity(replacement.len());
+    let mut chars = replacement.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some(d)) if d.is_ascii_digit() => {
+                converted.push_str(&format!("${{{}}}", d));
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                converted.push('\\');
+                chars.next();
+            }
+            (c, _) => converted.push(c),
+        }
+    }
+    converted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(expressions: &[&str], name: &str) -> String {
+        let subs: Vec<Substitution> = expressions.iter().map(|e| e.parse().unwrap()).collect();
+        substitute(&subs, name.as_ref()).into_string().unwrap()
+    }
+
+    #[test]
+    fn substitutions() {
+        assert_eq!(apply(&["s/a/b/"], "aaa"), "baa");
+        assert_eq!(apply(&["s/a/b/g"], "aaa"), "bbb");
+        assert_eq!(apply(&["s/A/b/gi"], "aAa"), "bbb");
+        assert_eq!(
+            apply(&[r"s/(\d+)-(\w+)/$2-\1/"], "01-intro.md"),
+            "intro-01.md"
+        );
+        assert_eq!(apply(&[r"s|/|\||g"], "a/b"), "a|b");
+        assert_eq!(apply(&["s/a/&-$$-$0/"], "a"), "&-$-a");
+        assert_eq!(
+            apply(&["s/ /_/g", r"s/\.jpeg$/.jpg/"], "a b.jpeg"),
+            "a_b.jpg"
+        );
+    }
+
+    #[test]
+    fn invalid_expressions() {
+        for expression in ["a/b/c/", "s/a/b", "s/a/b/x", "s/(/b/", "sabc"] {
+            assert!(
+                expression.parse::<Substitution>().is_err(),
+                "{}",
+                expression
+            );
+        }
+    }
+}
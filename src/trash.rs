@@ -0,0 +1,315 @@
+//! Keeping files that are overwritten by renames or deleted, so that undo can
+//! bring them back.
+//!
+//! Every operation that overwrites or deletes files gets its own directory
+//! below `trash/` in the state directory. The files are moved there before
+//! renaming, and the moves are recorded with the operation.
+//!
+//! On Unix, files on another file system than the state directory go to
+//! `.Trash-$uid/pipe-rename/` at the top of their own file system instead, as
+//! in the freedesktop.org trash specification, so they are not copied.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+
+use crate::backend::{FsBackend, RenameBackend};
+use crate::execute::execute_renames;
+use crate::history::state_dir;
+use crate::output::Events;
+use crate::plan::find_conflicts;
+use crate::Rename;
+
+/// A new trash directory for one operation below `state_dir`.
+pub fn trash_dir(state_dir: &Path) -> PathBuf {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos());
+    state_dir
+        .join("trash")
+        .join(format!("{}-{}", nanos, process::id()))
+}
+
+/// The moves that take the files overwritten by `renames`, and the `deleted`
+/// files, into `dir`.
+///
+/// Each file is kept in a numbered subdirectory, so files with the same name
+/// from different directories do not collide. Deleted files inside deleted
+/// directories go to the trash along with their directory.
+pub fn stash_moves(renames: &[Rename], deleted: &[PathBuf], dir: &Path) -> Vec<Rename> {
+    let overwritten = find_conflicts(renames)
+        .into_iter()
+        .map(|conflict| conflict.existing)
+        .filter(|existing| !deleted.contains(existing));
+    let deleted = deleted
+        .iter()
+        .filter(|file| !deleted.iter().any(|d| d != *file && file.starts_with(d)))
+        .cloned();
+    overwritten
+        .chain(deleted)
+        .enumerate()
+        .map(|(i, file)| Rename {
+            new: dir
+                .join(i.to_string())
+                .join(file.file_name().unwrap_or_default()),
+            original: file,
+        })
+        .collect()
+}
+
+/// The moves that keep the files overwritten by `renames`, and the `deleted`
+/// files, in a new trash directory on their file system.
+///
+/// The state directory is only needed if there are such files. Files that can
+/// only be kept by copying them to the state directory are passed to `warn`.
+pub fn trash_moves(
+    renames: &[Rename],
+    deleted: &[PathBuf],
+    warn: &mut dyn FnMut(&str),
+) -> anyhow::Result<Vec<Rename>> {
+    if stash_moves(renames, deleted, Path::new("")).is_empty() {
+        return Ok(vec![]);
+    }
+    let state_dir = state_dir().context("Could not find a trash directory to keep files in")?;
+    trash_moves_below(renames, deleted, &state_dir, warn)
+}
+
+fn trash_moves_below(
+    renames: &[Rename],
+    deleted: &[PathBuf],
+    state_dir: &Path,
+    warn: &mut dyn FnMut(&str),
+) -> anyhow::Result<Vec<Rename>> {
+    let dir = trash_dir(state_dir);
+    let mut moves = stash_moves(renames, deleted, &dir);
+    #[cfg(unix)]
+    for stashed in &mut moves {
+        let top = match mount::other_top(&stashed.original, state_dir)? {
+            Some(top) => top,
+            None => continue,
+        };
+        match mount::trash_root(&top) {
+            Ok(root) => {
+                let rel = stashed.new.strip_prefix(state_dir.join("trash"))?;
+                stashed.new = root.join(rel);
+            }
+            Err(e) => warn(&format!(
+                "copying {} to the trash in {}, since there is no trash on its file system: {}",
+                stashed.original.display(),
+                dir.display(),
+                e
+            )),
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = warn;
+    Ok(moves)
+}
+
+/// Finding the trash on the file system of a file.
+#[cfg(unix)]
+mod mount {
+    use std::fs::{self, DirBuilder};
+    use std::io;
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+    use std::path::{Path, PathBuf};
+
+    use crate::history::make_absolute;
+
+    /// The top directory of the file system of `file`, if it is another one
+    /// than that of `state_dir`.
+    pub fn other_top(file: &Path, state_dir: &Path) -> anyhow::Result<Option<PathBuf>> {
+        let file = make_absolute(file)?;
+        let dev = file.symlink_metadata()?.dev();
+        let state_dev = existing(state_dir)?.metadata()?.dev();
+        if dev == state_dev {
+            return Ok(None);
+        }
+        // The file itself may be a symbolic link, so only its directory is
+        // resolved.
+        let dir = fs::canonicalize(file.parent().unwrap_or(&file))?;
+        let mut top = dir.as_path();
+        while let Some(parent) = top.parent() {
+            if parent.metadata()?.dev() != dev {
+                break;
+            }
+            top = parent;
+        }
+        Ok(Some(top.to_path_buf()))
+    }
+
+    /// `.Trash-$uid/pipe-rename` in `top`, creating `.Trash-$uid` if needed.
+    pub fn trash_root(top: &Path) -> io::Result<PathBuf> {
+        // Safety: getuid cannot fail.
+        let uid = unsafe { libc::getuid() };
+        let trash = top.join(format!(".Trash-{}", uid));
+        match DirBuilder::new().mode(0o700).create(&trash) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                // Only a directory of our own can be trusted with the files.
+                let metadata = trash.symlink_metadata()?;
+                if !metadata.is_dir() || metadata.uid() != uid {
+                    return Err(io::Error::other(format!(
+                        "{} is not a directory of ours",
+                        trash.display()
+                    )));
+                }
+            }
+            result => result?,
+        }
+        Ok(trash.join("pipe-rename"))
+    }
+
+    /// The closest existing directory at or above `path`.
+    fn existing(path: &Path) -> io::Result<&Path> {
+        path.ancestors()
+            .find(|a| a.exists())
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+    }
+}
+
+/// Move the overwritten files into the trash, returning the warnings of the
+/// backend.
+pub fn stash(moves: &[Rename]) -> anyhow::Result<Vec<String>> {
+    let mut backend = FsBackend::default();
+    execute_renames(moves, &mut backend, true, &Events::default())?;
+    Ok(backend.take_warnings())
+}
+
+/// The moves that take stashed files back to where they were.
+pub fn restore_moves(moves: &[Rename]) -> Vec<Rename> {
+    moves
+        .iter()
+        .map(|m| Rename {
+            original: m.new.clone(),
+            new: m.original.clone(),
+        })
+        .collect()
+}
+
+/// Move stashed files back to where they were, and remove the emptied trash
+/// directory. Returns the warnings of the backend.
+pub fn restore(moves: &[Rename]) -> anyhow::Result<Vec<String>> {
+    let mut backend = FsBackend::default();
+    execute_renames(
+        &restore_moves(moves),
+        &mut backend,
+        false,
+        &Events::default(),
+    )?;
+    remove_dirs(moves, |dir| fs::remove_dir(dir));
+    Ok(backend.take_warnings())
+}
+
+/// Delete stashed files for good, when their operation is dropped from the
+/// history.
+pub fn purge(moves: &[Rename]) {
+    remove_dirs(moves, |dir| fs::remove_dir_all(dir));
+}
+
+fn remove_dirs(moves: &[Rename], remove: impl Fn(&Path) -> std::io::Result<()>) {
+    let dirs: Vec<&Path> = moves.iter().filter_map(|m| m.new.parent()).collect();
+    // Removing is best effort, the files may have been moved by hand.
+    for dir in &dirs {
+        let _ = remove(dir);
+    }
+    // The trash of an operation may be spread over several file systems.
+    let mut roots: Vec<&Path> = dirs.iter().filter_map(|d| d.parent()).collect();
+    roots.sort();
+    roots.dedup();
+    for root in roots {
+        let _ = remove(root);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stash_and_restore() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        fs::write(&a, "a")?;
+        fs::write(&b, "b")?;
+        let renames = [Rename {
+            original: a.clone(),
+            new: b.clone(),
+        }];
+
+        let trash = trash_dir(&dir.path().join("state"));
+        let moves = stash_moves(&renames, &[], &trash);
+        assert_eq!(moves.len(), 1);
+        stash(&moves)?;
+        assert!(!b.exists());
+        assert_eq!(fs::read_to_string(&moves[0].new)?, "b");
+
+        restore(&moves)?;
+        assert_eq!(fs::read_to_string(&b)?, "b");
+        assert!(!trash.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn stash_nested_deletions() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (d, x) = (dir.path().join("d"), dir.path().join("d/x"));
+        fs::create_dir(&d)?;
+        fs::write(&x, "x")?;
+
+        let trash = trash_dir(&dir.path().join("state"));
+        let moves = stash_moves(&[], &[x.clone(), d.clone()], &trash);
+        assert_eq!(moves.len(), 1);
+        assert_eq!(moves[0].original, d);
+        stash(&moves)?;
+        assert!(!d.exists());
+
+        restore(&moves)?;
+        assert_eq!(fs::read_to_string(&x)?, "x");
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stash_on_the_same_file_system() -> anyhow::Result<()> {
+        use std::os::unix::fs::MetadataExt;
+
+        let state = tempfile::tempdir()?;
+        let dir = match tempfile::tempdir_in("/dev/shm") {
+            Ok(dir) if dir.path().metadata()?.dev() != state.path().metadata()?.dev() => dir,
+            _ => {
+                eprintln!("skipping test: /dev/shm is not another file system");
+                return Ok(());
+            }
+        };
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        fs::write(&a, "a")?;
+        fs::write(&b, "b")?;
+        let renames = [Rename {
+            original: a,
+            new: b.clone(),
+        }];
+
+        let mut warnings = vec![];
+        let moves = trash_moves_below(&renames, &[], state.path(), &mut |w| {
+            warnings.push(w.to_string())
+        })?;
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        let trash = Path::new("/dev/shm")
+            .join(format!(".Trash-{}", unsafe { libc::getuid() }))
+            .join("pipe-rename");
+        assert!(moves[0].new.starts_with(&trash), "{:?}", moves);
+        stash(&moves)?;
+        assert!(!b.exists());
+
+        restore(&moves)?;
+        assert_eq!(fs::read_to_string(&b)?, "b");
+        assert!(!moves[0].new.parent().unwrap().parent().unwrap().exists());
+        // Leave the trash of other runs alone.
+        let _ = fs::remove_dir(&trash);
+        let _ = fs::remove_dir(trash.parent().unwrap());
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+//! Expanding directories into the entries they contain.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::{DirEntry, WalkBuilder};
+
+/// Options for expanding directories into the files they contain.
+#[cfg_attr(feature = "cli", derive(clap::Args))]
+#[derive(Clone, Debug, Default)]
+pub struct WalkOptions {
+    /// Expand directories recursively
+    #[cfg_attr(feature = "cli", clap(short, long))]
+    pub recursive: bool,
+
+    /// Descend at most N directories below the given ones
+    #[cfg_attr(feature = "cli", clap(long, value_name = "N", requires = "recursive"))]
+    pub max_depth: Option<usize>,
+
+    /// Only list entries whose name or path matches GLOB
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            value_name = "GLOB",
+            multiple_occurrences = true,
+            requires = "recursive"
+        )
+    )]
+    pub include: Vec<String>,
+
+    /// Skip entries whose name or path matches GLOB, and their contents
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long,
+            value_name = "GLOB",
+            multiple_occurrences = true,
+            requires = "recursive"
+        )
+    )]
+    pub exclude: Vec<String>,
+
+    /// Only list entries of the given type
+    #[cfg_attr(
+        feature = "cli",
+        clap(
+            long = "type",
+            arg_enum,
+            value_name = "TYPE",
+            multiple_occurrences = true,
+            requires = "recursive"
+        )
+    )]
+    pub file_type: Vec<FileType>,
+
+    /// Include hidden files and directories when expanding recursively
+    #[cfg_attr(feature = "cli", clap(long, requires = "recursive"))]
+    pub hidden: bool,
+
+    /// Skip files ignored by .gitignore, .ignore and global git excludes when
+    /// expanding directories with -r or a lone . or .., named files are kept
+    #[cfg_attr(feature = "cli", clap(long))]
+    pub respect_gitignore: bool,
+}
+
+#[cfg_attr(feature = "cli", derive(clap::ArgEnum))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileType {
+    /// Regular files
+    F,
+    /// Directories
+    D,
+    /// Symbolic links
+    L,
+}
+
+impl FileType {
+    fn matches(self, entry: &DirEntry) -> bool {
+        entry.file_type().is_some_and(|t| match self {
+            FileType::F => t.is_file(),
+            FileType::D => t.is_dir(),
+            FileType::L => t.is_symlink(),
+        })
+    }
+}
+
+fn glob_set(globs: &[String]) -> anyhow::Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(Glob::new(glob).with_context(|| format!("Invalid glob {:?}", glob))?);
+    }
+    Ok(builder.build()?)
+}
+
+/// Match globs against the file name or the path relative to the walked
+/// directory.
+fn is_match(set: &GlobSet, root: &Path, path: &Path) -> bool {
+    path.file_name().is_some_and(|name| set.is_match(name))
+        || path.strip_prefix(root).is_ok_and(|rel| set.is_match(rel))
+}
+
+/// List the entries below `root`, sorted by name within each directory.
+pub fn walk_dir(root: &Path, options: &WalkOptions) -> anyhow::Result<Vec<PathBuf>> {
+    let include = glob_set(&options.include)?;
+    let exclude = glob_set(&options.exclude)?;
+
+    let mut builder = WalkBuilder::new(root);
+    builder
+        .standard_filters(false)
+        .hidden(!options.hidden)
+        .max_depth(options.max_depth)
+        .sort_by_file_name(|a, b| a.cmp(b));
+    if options.respect_gitignore {
+        builder
+            .git_ignore(true)
+            .git_global(true)
+            .git_exclude(true)
+            .ignore(true)
+            .parents(true)
+            .require_git(false);
+    }
+
+    let root_path = root.to_path_buf();
+    let skip_git_dir = options.respect_gitignore;
+    builder.filter_entry(move |entry| {
+        if skip_git_dir && entry.file_name() == ".git" {
+            return false;
+        }
+        exclude.is_empty() || !is_match(&exclude, &root_path, entry.path())
+    });
+
+    let mut files = vec![];
+    for entry in builder.build() {
+        let entry = entry?;
+        if entry.depth() == 0 {
+            continue;
+        }
+        if !include.is_empty() && !is_match(&include, root, entry.path()) {
+            continue;
+        }
+        if !options.file_type.is_empty() && !options.file_type.iter().any(|t| t.matches(&entry)) {
+            continue;
+        }
+        files.push(entry.into_path());
+    }
+    Ok(files)
+}
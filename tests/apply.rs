@@ -0,0 +1,54 @@
+mod run;
+
+use std::fs;
+
+use run::TestCase;
+
+#[test]
+fn test_apply() -> anyhow::Result<()> {
+    let mut test_case = TestCase::new()?;
+    test_case.replace("1", "2")?;
+    test_case.replace("2", "3")?;
+
+    let plans = tempfile::tempdir()?;
+    let plan = plans.path().join("plan.json");
+    fs::write(
+        &plan,
+        r#"[{"original": "1", "new": "2"}, {"original": "2", "new": "3"}]"#,
+    )?;
+
+    test_case
+        .run_args(&["--apply", plan.to_str().unwrap()])?
+        .success();
+    test_case.assert_renamed()?;
+
+    test_case.run_args(&["--undo"])?.success();
+    test_case.assert_unchanged()?;
+
+    Ok(())
+}
+
+#[test]
+fn test_apply_revalidates() -> anyhow::Result<()> {
+    let mut test_case = TestCase::new()?;
+    test_case.replace("1", "2")?;
+    test_case.create("3")?;
+
+    let plans = tempfile::tempdir()?;
+    let plan = plans.path().join("plan.json");
+    let plan_arg = plan.to_str().unwrap();
+
+    fs::write(&plan, r#"[{"original": "1", "new": "3"}]"#)?;
+    test_case
+        .run_args(&["--apply", plan_arg])?
+        .failure()
+        .stderr("Error: Refusing to overwrite existing files.\n");
+
+    fs::write(&plan, r#"[{"original": "4", "new": "5"}]"#)?;
+    test_case
+        .run_args(&["--apply", plan_arg])?
+        .failure()
+        .stderr("Error: Nonexistent input files.\n");
+
+    Ok(())
+}
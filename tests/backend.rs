@@ -0,0 +1,114 @@
+mod run;
+
+use std::fs;
+
+use run::{git, installed, run_in_dir, tool};
+
+#[test]
+fn test_backend_record() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    for file in ["a", "b"] {
+        fs::write(dir.path().join(file), file)?;
+    }
+
+    let (assert, _) = run_in_dir(dir.path(), &["--backend", "record", "a", "b"], "", "b\na\n")?;
+    assert
+        .success()
+        .stdout(predicates::str::contains("no files were renamed"))
+        .stdout(predicates::str::contains("b -> .b.renamer-"));
+    assert_eq!(fs::read_to_string(dir.path().join("a"))?, "a");
+    assert_eq!(fs::read_to_string(dir.path().join("b"))?, "b");
+
+    Ok(())
+}
+
+#[test]
+fn test_backend_copy() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    fs::create_dir(dir.path().join("a"))?;
+    fs::write(dir.path().join("a/file"), "content")?;
+
+    let (assert, _) = run_in_dir(dir.path(), &["--backend", "copy", "a"], "", "b\n")?;
+    assert.success();
+    assert!(!dir.path().join("a").exists());
+    assert_eq!(fs::read_to_string(dir.path().join("b/file"))?, "content");
+
+    Ok(())
+}
+
+#[test]
+fn test_backend_git() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    if !installed("git") {
+        return Ok(());
+    }
+    git(dir.path(), &["init", "-q"])?;
+    fs::write(dir.path().join("a"), "")?;
+    git(dir.path(), &["add", "a"])?;
+    git(dir.path(), &["commit", "-q", "-m", "init"])?;
+
+    let (assert, _) = run_in_dir(dir.path(), &["--backend", "git", "a"], "", "b\n")?;
+    assert.success();
+    assert_eq!(git(dir.path(), &["status", "--porcelain"])?, "R  a -> b\n");
+
+    Ok(())
+}
+
+#[test]
+fn test_backend_hg() -> anyhow::Result<()> {
+    if !installed("hg") {
+        return Ok(());
+    }
+    let dir = tempfile::tempdir()?;
+    tool(dir.path(), "hg", &["init"])?;
+    fs::write(dir.path().join("a"), "")?;
+    tool(dir.path(), "hg", &["add", "a"])?;
+    tool(
+        dir.path(),
+        "hg",
+        &["commit", "-q", "-u", "test", "-m", "init"],
+    )?;
+
+    let (assert, _) = run_in_dir(dir.path(), &["--backend", "hg", "a"], "", "b\n")?;
+    assert.success();
+    assert_eq!(tool(dir.path(), "hg", &["status"])?, "A b\nR a\n");
+
+    Ok(())
+}
+
+#[test]
+fn test_backend_svn() -> anyhow::Result<()> {
+    if !installed("svn") || !installed("svnadmin") {
+        return Ok(());
+    }
+    let dir = tempfile::tempdir()?;
+    let wc = dir.path().join("wc");
+    tool(dir.path(), "svnadmin", &["create", "repo"])?;
+    let url = format!("file://{}", dir.path().join("repo").display());
+    tool(dir.path(), "svn", &["checkout", "-q", &url, "wc"])?;
+    fs::write(wc.join("a"), "")?;
+    tool(&wc, "svn", &["add", "-q", "a"])?;
+    tool(&wc, "svn", &["commit", "-q", "-m", "init"])?;
+
+    let (assert, _) = run_in_dir(&wc, &["--backend", "svn", "a"], "", "b\n")?;
+    assert.success();
+    let status = tool(&wc, "svn", &["status"])?;
+    assert!(status.lines().any(|line| line == "D       a"), "{}", status);
+    assert!(status.lines().any(|line| line == "A  +    b"), "{}", status);
+
+    Ok(())
+}
+
+#[test]
+fn test_backend_git_conflicts_with_git_flag() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    fs::write(dir.path().join("a"), "")?;
+
+    let (assert, _) = run_in_dir(dir.path(), &["--git", "--backend", "git", "a"], "", "")?;
+    assert
+        .failure()
+        .stderr(predicates::str::contains("--git cannot be used"));
+    assert!(dir.path().join("a").exists());
+
+    Ok(())
+}
@@ -0,0 +1,132 @@
+mod run;
+
+use std::fs;
+
+use run::run_in_dir_with_state;
+
+#[test]
+fn test_on_conflict_backup() -> anyhow::Result<()> {
+    let (dir, state) = (tempfile::tempdir()?, tempfile::tempdir()?);
+    for file in ["a", "b", "b~"] {
+        fs::write(dir.path().join(file), file)?;
+    }
+
+    let args = ["--on-conflict", "backup", "--regex", "s/a/b/", "a"];
+    let (assert, _) = run_in_dir_with_state(dir.path(), state.path(), &args, "", "")?;
+    assert.success();
+    assert!(!dir.path().join("a").exists());
+    assert_eq!(fs::read_to_string(dir.path().join("b"))?, "a");
+    assert_eq!(fs::read_to_string(dir.path().join("b.orig"))?, "b");
+    assert_eq!(fs::read_to_string(dir.path().join("b~"))?, "b~");
+
+    let (assert, _) = run_in_dir_with_state(dir.path(), state.path(), &["--undo"], "", "")?;
+    assert.success();
+    assert_eq!(fs::read_to_string(dir.path().join("a"))?, "a");
+    assert_eq!(fs::read_to_string(dir.path().join("b"))?, "b");
+    assert!(!dir.path().join("b.orig").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_on_conflict_number() -> anyhow::Result<()> {
+    let (dir, state) = (tempfile::tempdir()?, tempfile::tempdir()?);
+    for file in ["a.txt", "b.txt", "b (1).txt"] {
+        fs::write(dir.path().join(file), file)?;
+    }
+
+    let args = [
+        "--on-conflict",
+        "number",
+        "--print0",
+        "--regex",
+        "s/a/b/",
+        "a.txt",
+    ];
+    let (assert, _) = run_in_dir_with_state(dir.path(), state.path(), &args, "", "")?;
+    assert.success().stdout("b (2).txt\0");
+    assert_eq!(fs::read_to_string(dir.path().join("b (2).txt"))?, "a.txt");
+    assert_eq!(fs::read_to_string(dir.path().join("b.txt"))?, "b.txt");
+
+    Ok(())
+}
+
+#[test]
+fn test_on_conflict_skip() -> anyhow::Result<()> {
+    let (dir, state) = (tempfile::tempdir()?, tempfile::tempdir()?);
+    for file in ["a", "b", "c"] {
+        fs::write(dir.path().join(file), file)?;
+    }
+
+    let args = [
+        "--on-conflict",
+        "skip",
+        "--regex",
+        "s/a/b/",
+        "--regex",
+        "s/c/d/",
+        "a",
+        "c",
+    ];
+    let (assert, _) = run_in_dir_with_state(dir.path(), state.path(), &args, "", "")?;
+    assert.success();
+    assert_eq!(fs::read_to_string(dir.path().join("a"))?, "a");
+    assert_eq!(fs::read_to_string(dir.path().join("b"))?, "b");
+    assert_eq!(fs::read_to_string(dir.path().join("d"))?, "c");
+
+    // Nothing is left to rename when every rename is skipped.
+    let args = ["--on-conflict", "skip", "--regex", "s/a/b/", "a"];
+    let (assert, _) = run_in_dir_with_state(dir.path(), state.path(), &args, "", "")?;
+    assert.failure();
+
+    Ok(())
+}
+
+#[test]
+fn test_on_conflict_ask_with_yes() -> anyhow::Result<()> {
+    let (dir, state) = (tempfile::tempdir()?, tempfile::tempdir()?);
+    for file in ["a", "b"] {
+        fs::write(dir.path().join(file), file)?;
+    }
+
+    let args = ["--on-conflict", "ask", "--regex", "s/a/b/", "a"];
+    let (assert, _) = run_in_dir_with_state(dir.path(), state.path(), &args, "", "")?;
+    assert
+        .failure()
+        .stderr(predicates::str::contains("Cannot ask about conflicts"));
+    assert_eq!(fs::read_to_string(dir.path().join("b"))?, "b");
+
+    Ok(())
+}
+
+#[test]
+fn test_overwrite_undo() -> anyhow::Result<()> {
+    let (dir, state) = (tempfile::tempdir()?, tempfile::tempdir()?);
+    for file in ["a", "b"] {
+        fs::write(dir.path().join(file), file)?;
+    }
+
+    let args = ["--force", "--regex", "s/a/b/", "a"];
+    let (assert, _) = run_in_dir_with_state(dir.path(), state.path(), &args, "", "")?;
+    assert.success();
+    assert!(!dir.path().join("a").exists());
+    assert_eq!(fs::read_to_string(dir.path().join("b"))?, "a");
+
+    let (assert, _) = run_in_dir_with_state(dir.path(), state.path(), &["--undo"], "", "")?;
+    assert.success();
+    assert_eq!(fs::read_to_string(dir.path().join("a"))?, "a");
+    assert_eq!(fs::read_to_string(dir.path().join("b"))?, "b");
+    let trash = state.path().join("pipe-rename/trash");
+    assert_eq!(fs::read_dir(&trash)?.count(), 0);
+
+    let (assert, _) = run_in_dir_with_state(dir.path(), state.path(), &["--redo"], "", "")?;
+    assert.success();
+    assert_eq!(fs::read_to_string(dir.path().join("b"))?, "a");
+    // The trash is recorded with absolute paths.
+    let (assert, _) =
+        run_in_dir_with_state(state.path(), state.path(), &["--undo", "--any-dir"], "", "")?;
+    assert.success();
+    assert_eq!(fs::read_to_string(dir.path().join("b"))?, "b");
+
+    Ok(())
+}
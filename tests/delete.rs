@@ -0,0 +1,88 @@
+mod run;
+
+use std::fs;
+
+use run::{run_in_dir, run_in_dir_with_state};
+
+#[test]
+fn test_allow_delete() -> anyhow::Result<()> {
+    let (dir, state) = (tempfile::tempdir()?, tempfile::tempdir()?);
+    for file in ["a", "b", "c"] {
+        fs::write(dir.path().join(file), file)?;
+    }
+
+    // Lines can be moved around, they are matched by their id.
+    let (assert, editor_input) = run_in_dir_with_state(
+        dir.path(),
+        state.path(),
+        &["--allow-delete", "a", "b", "c"],
+        "",
+        "0003\tc\n0001\tx\n",
+    )?;
+    assert.success();
+    assert_eq!(editor_input, "0001\ta\n0002\tb\n0003\tc");
+    assert!(!dir.path().join("b").exists());
+    assert_eq!(fs::read_to_string(dir.path().join("x"))?, "a");
+
+    let (assert, _) = run_in_dir_with_state(dir.path(), state.path(), &["--undo"], "", "")?;
+    assert.success().stdout("Restored 2 files.\n");
+    for file in ["a", "b", "c"] {
+        assert_eq!(fs::read_to_string(dir.path().join(file))?, file);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_allow_delete_replaced() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    for file in ["a", "b"] {
+        fs::write(dir.path().join(file), file)?;
+    }
+
+    // Renaming onto a deleted file is no conflict.
+    let (assert, _) = run_in_dir(dir.path(), &["--allow-delete", "a", "b"], "", "0001\tb\n")?;
+    assert.success();
+    assert!(!dir.path().join("a").exists());
+    assert_eq!(fs::read_to_string(dir.path().join("b"))?, "a");
+
+    Ok(())
+}
+
+#[test]
+fn test_allow_delete_invalid_ids() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    for file in ["a", "b"] {
+        fs::write(dir.path().join(file), file)?;
+    }
+
+    for edited in ["a\n", "0001\tx\n0001\ty\n", "0003\tc\n"] {
+        let (assert, _) = run_in_dir(dir.path(), &["--allow-delete", "a", "b"], "", edited)?;
+        assert.failure();
+    }
+
+    // Without --allow-delete, removing a line is an error.
+    let (assert, _) = run_in_dir(dir.path(), &["a", "b"], "", "a\n")?;
+    assert.failure();
+    assert!(dir.path().join("b").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_allow_delete_nested() -> anyhow::Result<()> {
+    let (dir, state) = (tempfile::tempdir()?, tempfile::tempdir()?);
+    fs::create_dir(dir.path().join("d"))?;
+    fs::write(dir.path().join("d/x"), "x")?;
+
+    let args = ["--allow-delete", "d", "d/x"];
+    let (assert, _) = run_in_dir_with_state(dir.path(), state.path(), &args, "", "")?;
+    assert.success();
+    assert!(!dir.path().join("d").exists());
+
+    let (assert, _) = run_in_dir_with_state(dir.path(), state.path(), &["--undo"], "", "")?;
+    assert.success();
+    assert_eq!(fs::read_to_string(dir.path().join("d/x"))?, "x");
+
+    Ok(())
+}
@@ -0,0 +1,52 @@
+mod run;
+
+use std::fs;
+
+use run::run_in_dir;
+
+#[test]
+fn test_dry_run() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    for file in ["a", "b"] {
+        fs::write(dir.path().join(file), "")?;
+    }
+
+    let (assert, _) = run_in_dir(dir.path(), &["--dry-run", "a", "b"], "", "b\nc\n")?;
+    assert
+        .success()
+        .stdout(predicates::str::contains("a -> b"))
+        .stdout(predicates::str::contains("Dry run, no files were renamed."));
+    assert!(dir.path().join("a").is_file());
+    assert!(!dir.path().join("c").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_dry_run_formats() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    for file in ["a", "b"] {
+        fs::write(dir.path().join(file), "")?;
+    }
+
+    let args = ["--dry-run", "--format", "shell", "a", "b"];
+    let (assert, _) = run_in_dir(dir.path(), &args, "", "b\nc d\n")?;
+    assert
+        .success()
+        .stdout("#!/bin/sh\nset -e\nmv -- b 'c d'\nmv -- a b\n");
+
+    let args = ["--dry-run", "--format", "tsv", "a", "b"];
+    let (assert, _) = run_in_dir(dir.path(), &args, "", "b\nc\n")?;
+    assert.success().stdout("a\tb\nb\tc\n");
+
+    let args = ["--dry-run", "--format", "json", "a", "b"];
+    let (assert, _) = run_in_dir(dir.path(), &args, "", "a\nc\n")?;
+    let output = String::from_utf8(assert.success().get_output().stdout.clone())?;
+    let plan: serde_json::Value = serde_json::from_str(&output)?;
+    assert_eq!(plan, serde_json::json!([{ "original": "b", "new": "c" }]));
+
+    let (assert, _) = run_in_dir(dir.path(), &["--format", "json", "a"], "", "")?;
+    assert.failure();
+
+    Ok(())
+}
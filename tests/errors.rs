@@ -1,6 +1,6 @@
 mod run;
 
-use run::{run_with_env, TestCase, EMPTY};
+use run::{run_in_dir, run_with_env, TestCase, EMPTY};
 
 #[test]
 fn test_no_input() -> anyhow::Result<()> {
@@ -52,7 +52,7 @@ fn test_duplicate_output() -> anyhow::Result<()> {
 fn test_rename() -> anyhow::Result<()> {
     let mut test_case = TestCase::new()?;
     test_case.replace("1", "2")?;
-    test_case.replace("2", "3")?;
+    test_case.create("2")?;
 
     let assert = test_case.run()?;
     assert
@@ -83,9 +83,55 @@ fn test_new_dir() -> anyhow::Result<()> {
     test_case.replace("1", "a/1")?;
 
     let assert = test_case.run()?;
-    assert
+    assert.failure().stderr(predicates::str::ends_with(
+        "Caused by:\n    No such file or directory (os error 2)\n",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_rollback() -> anyhow::Result<()> {
+    let mut test_case = TestCase::new()?;
+    test_case.replace("1", "2")?;
+    test_case.replace("3", "a/3")?;
+
+    let assert = test_case.run()?;
+    assert.failure().stderr(predicates::str::contains(
+        "(step 2 of 2), rolled back 1 completed renames",
+    ));
+    test_case.assert_unchanged()?;
+
+    Ok(())
+}
+
+#[test]
+fn test_any_dir_without_history_mode() -> anyhow::Result<()> {
+    let mut test_case = TestCase::new()?;
+    test_case.replace("1", "2")?;
+
+    test_case
+        .run_args(&["--any-dir", "1"])?
         .failure()
-        .stderr("Error: No such file or directory (os error 2)\n");
+        .stderr(predicates::str::contains(
+            "required arguments were not provided",
+        ));
+    test_case.assert_unchanged()?;
+
+    Ok(())
+}
+
+#[test]
+fn test_empty_input_line() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    for file in ["a", "b"] {
+        std::fs::write(dir.path().join(file), "")?;
+    }
 
+    // An empty line on stdin is a file name like any other.
+    let (assert, _) = run_in_dir(dir.path(), &[], "a\n\nb\n", "")?;
+    assert.failure().stderr(predicates::str::ends_with(
+        "Error: Nonexistent input files.\n",
+    ));
     Ok(())
 }
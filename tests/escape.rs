@@ -0,0 +1,74 @@
+mod run;
+
+use std::fs;
+
+use run::run_in_dir;
+
+#[test]
+fn test_escape_c() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    fs::write(dir.path().join("new\nline"), "")?;
+    fs::write(dir.path().join("trailing "), "")?;
+
+    let (assert, editor_input) = run_in_dir(
+        dir.path(),
+        &["-0", "--escape", "c"],
+        "new\nline\0trailing \0",
+        "tab\\there\ntrailing\\x20\\x20",
+    )?;
+    assert.success();
+
+    assert_eq!(editor_input, "new\\nline\ntrailing\\x20");
+    assert!(dir.path().join("tab\there").is_file());
+    assert!(dir.path().join("trailing  ").is_file());
+
+    Ok(())
+}
+
+#[test]
+fn test_escape_shell() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    fs::write(dir.path().join("it's"), "")?;
+
+    let (assert, editor_input) =
+        run_in_dir(dir.path(), &["--escape", "shell"], "it's\n", "'it is '")?;
+    assert.success();
+
+    assert_eq!(editor_input, "'it'\\''s'");
+    assert!(dir.path().join("it is ").is_file());
+
+    Ok(())
+}
+
+#[test]
+fn test_escape_invalid() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    fs::write(dir.path().join("a"), "")?;
+
+    let (assert, _) = run_in_dir(dir.path(), &["--escape", "c"], "a\n", "b\\q")?;
+    assert
+        .failure()
+        .stderr("Error: Invalid file name on line 1: unknown escape sequence \\q.\n");
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_invalid_utf8() -> anyhow::Result<()> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = tempfile::tempdir()?;
+    fs::write(dir.path().join(OsStr::from_bytes(b"caf\xe9")), "")?;
+
+    let (assert, editor_input) = run_in_dir(dir.path(), &[], &b"caf\xe9\n"[..], "café")?;
+    assert.success().stdout(predicates::str::starts_with(
+        "Some file names are not valid UTF-8, escaping them with `--escape c`.\n",
+    ));
+
+    assert_eq!(editor_input, "caf\\xe9");
+    assert!(dir.path().join("café").is_file());
+
+    Ok(())
+}
@@ -0,0 +1,46 @@
+mod run;
+
+use std::fs;
+
+use run::run_in_dir;
+
+#[test]
+fn test_filter() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    for file in ["A.TXT", "B.TXT"] {
+        fs::write(dir.path().join(file), "")?;
+    }
+
+    let (assert, editor_input) = run_in_dir(
+        dir.path(),
+        &["--filter", "tr A-Z a-z", "A.TXT", "B.TXT"],
+        "",
+        "",
+    )?;
+    assert.success();
+    assert!(editor_input.is_empty(), "editor was opened");
+    assert!(dir.path().join("a.txt").is_file());
+    assert!(dir.path().join("b.txt").is_file());
+
+    Ok(())
+}
+
+#[test]
+fn test_filter_unequal_lines() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    for file in ["a", "b"] {
+        fs::write(dir.path().join(file), "")?;
+    }
+
+    let (assert, _) = run_in_dir(dir.path(), &["--filter", "head -n 1", "a", "b"], "", "")?;
+    assert
+        .failure()
+        .stderr(predicates::str::contains("Unequal number of files."));
+
+    let (assert, _) = run_in_dir(dir.path(), &["--filter", "false", "a", "b"], "", "")?;
+    assert
+        .failure()
+        .stderr(predicates::str::contains("Filter command exited with"));
+
+    Ok(())
+}
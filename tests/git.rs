@@ -0,0 +1,119 @@
+mod run;
+
+use std::fs;
+
+use run::{git, installed, run_in_dir, run_in_dir_with_state};
+
+#[test]
+fn test_git() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let state = tempfile::tempdir()?;
+    if !installed("git") {
+        return Ok(());
+    }
+    git(dir.path(), &["init", "-q"])?;
+    fs::create_dir(dir.path().join("d"))?;
+    for file in ["a", "d/x", "u"] {
+        fs::write(dir.path().join(file), "1\n2\n3\n4\n5\n")?;
+    }
+    git(dir.path(), &["add", "a", "d"])?;
+    git(dir.path(), &["commit", "-q", "-m", "init"])?;
+    // Staged and unstaged changes stay as they are.
+    fs::write(dir.path().join("a"), "1\n2\n3\n4\n5\nstaged\n")?;
+    git(dir.path(), &["add", "a"])?;
+    fs::write(dir.path().join("a"), "unstaged")?;
+
+    let args = [
+        "--git", "--regex", "s/^a$/b/", "--regex", "s/^d/e/", "--regex", "s/^u$/v/", "a", "d", "u",
+    ];
+    let (assert, _) = run_in_dir_with_state(dir.path(), state.path(), &args, "", "")?;
+    assert.success();
+    assert_eq!(
+        git(dir.path(), &["status", "--porcelain"])?,
+        "RM a -> b\nR  d/x -> e/x\n?? v\n"
+    );
+    assert!(git(dir.path(), &["show", ":b"])?.ends_with("staged\n"));
+
+    let (assert, _) = run_in_dir_with_state(dir.path(), state.path(), &["--undo"], "", "")?;
+    assert.success();
+    assert_eq!(git(dir.path(), &["status", "--porcelain"])?, "MM a\n?? u\n");
+
+    Ok(())
+}
+
+#[test]
+fn test_git_undo_overwrite() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let state = tempfile::tempdir()?;
+    if !installed("git") {
+        return Ok(());
+    }
+    git(dir.path(), &["init", "-q"])?;
+    for file in ["a", "b"] {
+        fs::write(dir.path().join(file), file)?;
+    }
+    git(dir.path(), &["add", "a", "b"])?;
+    git(dir.path(), &["commit", "-q", "-m", "init"])?;
+
+    let args = ["--git", "--force", "a"];
+    let (assert, _) = run_in_dir_with_state(dir.path(), state.path(), &args, "", "b\n")?;
+    assert.success();
+    assert_eq!(git(dir.path(), &["status", "--porcelain"])?, "D  a\nM  b\n");
+
+    // The overwritten file gets its index entry back.
+    let (assert, _) = run_in_dir_with_state(dir.path(), state.path(), &["--undo"], "", "")?;
+    assert.success();
+    assert_eq!(git(dir.path(), &["status", "--porcelain"])?, "");
+    assert_eq!(fs::read_to_string(dir.path().join("b"))?, "b");
+
+    Ok(())
+}
+
+#[test]
+fn test_git_outside_repository() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let repo = dir.path().join("repo");
+    fs::create_dir(&repo)?;
+    if !installed("git") {
+        return Ok(());
+    }
+    git(&repo, &["init", "-q"])?;
+    fs::write(repo.join("a"), "")?;
+    git(&repo, &["add", "a"])?;
+    git(&repo, &["commit", "-q", "-m", "init"])?;
+
+    // The repository is found from the renamed file, not the current directory.
+    let (assert, _) = run_in_dir(dir.path(), &["--git", "repo/a"], "", "repo/b\n")?;
+    assert.success();
+    assert_eq!(git(&repo, &["status", "--porcelain"])?, "R  a -> b\n");
+
+    Ok(())
+}
+
+#[test]
+fn test_git_unmerged() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    if !installed("git") {
+        return Ok(());
+    }
+    git(dir.path(), &["init", "-q"])?;
+    fs::write(dir.path().join("a"), "base")?;
+    git(dir.path(), &["add", "a"])?;
+    git(dir.path(), &["commit", "-q", "-m", "base"])?;
+    git(dir.path(), &["checkout", "-q", "-b", "other"])?;
+    fs::write(dir.path().join("a"), "other")?;
+    git(dir.path(), &["commit", "-q", "-am", "other"])?;
+    git(dir.path(), &["checkout", "-q", "-"])?;
+    fs::write(dir.path().join("a"), "main")?;
+    git(dir.path(), &["commit", "-q", "-am", "main"])?;
+    assert!(git(dir.path(), &["merge", "-q", "other"]).is_err());
+
+    let (assert, _) = run_in_dir(dir.path(), &["--git", "a"], "", "b\n")?;
+    assert
+        .failure()
+        .stderr(predicates::str::contains("a has merge conflicts"));
+    assert!(dir.path().join("a").exists());
+    assert_eq!(git(dir.path(), &["status", "--porcelain"])?, "UU a\n");
+
+    Ok(())
+}
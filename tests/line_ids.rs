@@ -0,0 +1,66 @@
+mod run;
+
+use std::fs;
+
+use run::run_in_dir;
+
+#[test]
+fn test_line_ids() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    for file in ["b", "a", "c"] {
+        fs::write(dir.path().join(file), file)?;
+    }
+
+    // The lines are sorted by name, as with `:sort` in vim.
+    let (assert, editor_input) = run_in_dir(
+        dir.path(),
+        &["--line-ids", "b", "a", "c"],
+        "",
+        "0002\ta\n0001\tb2\n0003\tc\n",
+    )?;
+    assert.success();
+    assert_eq!(editor_input, "0001\tb\n0002\ta\n0003\tc");
+    assert_eq!(fs::read_to_string(dir.path().join("a"))?, "a");
+    assert_eq!(fs::read_to_string(dir.path().join("b2"))?, "b");
+    assert!(!dir.path().join("b").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_line_ids_missing_line() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    for file in ["a", "b"] {
+        fs::write(dir.path().join(file), file)?;
+    }
+
+    let (assert, _) = run_in_dir(dir.path(), &["--line-ids", "a", "b"], "", "0001\tx\n")?;
+    assert
+        .failure()
+        .stderr(predicates::str::contains("--allow-delete"));
+    assert!(dir.path().join("a").exists());
+    assert!(dir.path().join("b").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_line_ids_without_file_name() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    fs::create_dir(dir.path().join("d"))?;
+    fs::write(dir.path().join("d/a"), "a")?;
+
+    // `..` has no file name to edit, which must not delete it.
+    let (assert, _) = run_in_dir(
+        &dir.path().join("d"),
+        &["--allow-delete", "--filenames-only", "a", ".."],
+        "",
+        "0001\tb\n",
+    )?;
+    assert
+        .failure()
+        .stderr(predicates::str::contains("line 2: no file name"));
+    assert!(dir.path().join("d/a").exists());
+
+    Ok(())
+}
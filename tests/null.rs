@@ -0,0 +1,40 @@
+mod run;
+
+use std::fs;
+
+use run::run_in_dir;
+
+#[test]
+fn test_null_separated() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    fs::write(dir.path().join("a\nb"), "")?;
+    fs::write(dir.path().join("c"), "")?;
+
+    // Names with line breaks are escaped in the editor.
+    let (assert, editor_input) =
+        run_in_dir(dir.path(), &["--null", "--print0"], "a\nb\0c\0", "x\\ny\nc")?;
+    assert.success().stdout("x\ny\0c\0");
+
+    assert_eq!(editor_input, "a\\nb\nc");
+    assert!(dir.path().join("x\ny").is_file());
+
+    Ok(())
+}
+
+#[test]
+fn test_print0_follows_renamed_directories() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    fs::create_dir(dir.path().join("dir"))?;
+    for file in ["dir/b", "dir/c"] {
+        fs::write(dir.path().join(file), "")?;
+    }
+
+    // `dir/b` moves along with its directory, `dir/c` is rebased onto it.
+    let args = ["--print0", "dir", "dir/b", "dir/c"];
+    let (assert, _) = run_in_dir(dir.path(), &args, "", "new\ndir/b\ndir/d")?;
+    assert.success().stdout("new\0new/b\0new/d\0");
+    assert!(dir.path().join("new/b").is_file());
+    assert!(dir.path().join("new/d").is_file());
+
+    Ok(())
+}
@@ -0,0 +1,116 @@
+mod run;
+
+use std::fs;
+
+use run::run_in_dir;
+use serde_json::{json, Value};
+
+fn events(output: &[u8]) -> anyhow::Result<Vec<Value>> {
+    let output = String::from_utf8(output.to_vec())?;
+    Ok(output
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?)
+}
+
+#[test]
+fn test_json_output() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    for file in ["1", "2"] {
+        fs::write(dir.path().join(file), "")?;
+    }
+
+    let (assert, _) = run_in_dir(dir.path(), &["--output", "json", "1", "2"], "", "2\n3\n")?;
+    let output = assert.success().get_output().stdout.clone();
+    assert_eq!(
+        events(&output)?,
+        [
+            json!({"event": "plan", "renames": [
+                {"original": "1", "new": "2"},
+                {"original": "2", "new": "3"},
+            ], "deleted": []}),
+            json!({"event": "rename-done", "original": "2", "new": "3"}),
+            json!({"event": "rename-done", "original": "1", "new": "2"}),
+            json!({"event": "summary", "renamed": 2, "error": null}),
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_json_conflict() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    for file in ["1", "2"] {
+        fs::write(dir.path().join(file), "")?;
+    }
+
+    let (assert, _) = run_in_dir(dir.path(), &["--output", "json", "1"], "", "2\n")?;
+    let output = assert.failure().get_output().stdout.clone();
+    let events = events(&output)?;
+    assert_eq!(
+        events[1],
+        json!({"event": "conflict", "original": "1", "new": "2"})
+    );
+    // Refusing to overwrite still ends with a summary.
+    assert_eq!(
+        events[2..],
+        [
+            json!({"event": "summary", "renamed": 0, "error": "Refusing to overwrite existing files."})
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_json_record() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    fs::write(dir.path().join("1"), "")?;
+
+    let args = ["--output", "json", "--backend", "record", "1"];
+    let (assert, _) = run_in_dir(dir.path(), &args, "", "2\n")?;
+    let output = assert.success().get_output().stdout.clone();
+    // Nothing is renamed, so only the plan is reported.
+    assert_eq!(
+        events(&output)?,
+        [json!({"event": "plan", "renames": [{"original": "1", "new": "2"}], "deleted": []})]
+    );
+    assert!(dir.path().join("1").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_json_conflicts_with_other_output() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    fs::write(dir.path().join("1"), "")?;
+
+    for args in [
+        &["--output", "json", "--print0", "1"][..],
+        &["--output", "json", "--dry-run", "--format", "json", "1"],
+    ] {
+        let (assert, _) = run_in_dir(dir.path(), args, "", "")?;
+        assert.failure();
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_json_deleted() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    for file in ["1", "2"] {
+        fs::write(dir.path().join(file), "")?;
+    }
+
+    let args = ["--output", "json", "--allow-delete", "1", "2"];
+    let (assert, _) = run_in_dir(dir.path(), &args, "", "0001\t3\n")?;
+    let output = assert.success().get_output().stdout.clone();
+    assert_eq!(
+        events(&output)?[0],
+        json!({"event": "plan", "renames": [{"original": "1", "new": "3"}], "deleted": ["2"]})
+    );
+
+    Ok(())
+}
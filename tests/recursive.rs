@@ -0,0 +1,118 @@
+mod run;
+
+use std::fs;
+
+use run::run_in_dir;
+
+fn create_tree(dir: &std::path::Path) -> anyhow::Result<()> {
+    for dir_name in ["a", "a/deep", "b", ".hidden"] {
+        fs::create_dir(dir.join(dir_name))?;
+    }
+    for file in [
+        "a/1",
+        "a/2.txt",
+        "a/deep/3",
+        "a/.4",
+        "b/5",
+        ".hidden/6",
+        "7",
+    ] {
+        fs::write(dir.join(file), "")?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_recursive() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    create_tree(dir.path())?;
+
+    let (assert, editor_input) = run_in_dir(
+        dir.path(),
+        &["-r", "a", "7"],
+        "",
+        "7\na/x\na/2.txt\na/deep\na/deep/y",
+    )?;
+    assert.success();
+    assert_eq!(editor_input, "7\na/1\na/2.txt\na/deep\na/deep/3");
+    assert!(dir.path().join("a/x").is_file());
+    assert!(dir.path().join("a/deep/y").is_file());
+
+    Ok(())
+}
+
+#[test]
+fn test_recursive_filters() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    create_tree(dir.path())?;
+
+    let args = [
+        "-r",
+        "--type",
+        "f",
+        "--exclude",
+        "*.txt",
+        "--max-depth",
+        "1",
+        "a",
+        "b",
+    ];
+    let (assert, editor_input) = run_in_dir(dir.path(), &args, "", "a/x\nb/5")?;
+    assert.success();
+    assert_eq!(editor_input, "a/1\nb/5");
+    assert!(dir.path().join("a/x").is_file());
+
+    let args = ["-r", "--hidden", "--include", "*[0-9]", "."];
+    let (_, editor_input) = run_in_dir(dir.path(), &args, "", "")?;
+    assert_eq!(editor_input, "./.hidden/6\n./7\n./a/.4\n./a/deep/3\n./b/5");
+
+    Ok(())
+}
+
+#[test]
+fn test_recursive_requires_flag() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+
+    let (assert, _) = run_in_dir(dir.path(), &["--max-depth", "1", "."], "", "")?;
+    assert
+        .failure()
+        .stderr(predicates::str::contains("--recursive"));
+
+    Ok(())
+}
+
+#[test]
+fn test_respect_gitignore() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    for dir_name in [".git", "build", "src"] {
+        fs::create_dir(dir.path().join(dir_name))?;
+    }
+    for file in [
+        ".git/config",
+        "build/out",
+        "src/main.log",
+        "src/main.rs",
+        "b",
+    ] {
+        fs::write(dir.path().join(file), "")?;
+    }
+    fs::write(dir.path().join(".gitignore"), "*.log\nbuild/\n")?;
+
+    let args = ["-r", "--respect-gitignore", "--type", "f", "."];
+    let (_, editor_input) = run_in_dir(dir.path(), &args, "", "")?;
+    assert_eq!(editor_input, "./b\n./src/main.rs");
+
+    let args = ["-r", "--respect-gitignore", "--hidden", "--type", "f", "."];
+    let (_, editor_input) = run_in_dir(dir.path(), &args, "", "")?;
+    assert_eq!(editor_input, "./.gitignore\n./b\n./src/main.rs");
+
+    let (_, editor_input) = run_in_dir(dir.path(), &["--respect-gitignore", "."], "", "")?;
+    assert_eq!(editor_input, "./.gitignore\n./b\n./src");
+
+    // Named files are not expanded, so they are kept even if ignored.
+    let args = ["--respect-gitignore", "src/main.log", "b"];
+    let (_, editor_input) = run_in_dir(dir.path(), &args, "", "")?;
+    assert_eq!(editor_input, "src/main.log\nb");
+
+    Ok(())
+}
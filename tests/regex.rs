@@ -0,0 +1,59 @@
+mod run;
+
+use std::fs;
+
+use run::run_in_dir;
+
+#[test]
+fn test_regex() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    for file in ["01-intro.MD", "02-setup.md", "notes.txt"] {
+        fs::write(dir.path().join(file), "")?;
+    }
+
+    let (assert, editor_input) = run_in_dir(
+        dir.path(),
+        &[
+            "--regex",
+            r"s/^(\d+)-(\w+)/\2-$1/",
+            "--regex",
+            "s/md$/markdown/i",
+            "01-intro.MD",
+            "02-setup.md",
+            "notes.txt",
+        ],
+        "",
+        "",
+    )?;
+    assert.success();
+    assert!(editor_input.is_empty(), "editor was opened");
+
+    let mut files: Vec<_> = fs::read_dir(dir.path())?
+        .map(|e| e.map(|e| e.file_name()))
+        .collect::<Result<_, _>>()?;
+    files.sort();
+    assert_eq!(
+        files,
+        ["intro-01.markdown", "notes.txt", "setup-02.markdown"]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_regex_invalid() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    fs::write(dir.path().join("a"), "")?;
+
+    let (assert, _) = run_in_dir(dir.path(), &["--regex", "s/a/b/q", "a"], "", "")?;
+    assert
+        .failure()
+        .stderr(predicates::str::contains("unknown flag 'q'"));
+
+    let (assert, _) = run_in_dir(dir.path(), &["--regex", "s/x/y/", "a"], "", "")?;
+    assert
+        .failure()
+        .stderr(predicates::str::contains("No replacements found."));
+
+    Ok(())
+}
@@ -1,6 +1,8 @@
 mod run;
 
-use run::TestCase;
+use std::fs;
+
+use run::{run_in_dir, TestCase};
 
 #[test]
 fn test_one_file() -> anyhow::Result<()> {
@@ -47,3 +49,63 @@ fn test_option() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_chain() -> anyhow::Result<()> {
+    let mut test_case = TestCase::new()?;
+    test_case.replace("1", "2")?;
+    test_case.replace("2", "3")?;
+
+    test_case.assert_run()?;
+    test_case.assert_renamed()?;
+
+    Ok(())
+}
+
+#[test]
+fn test_swap() -> anyhow::Result<()> {
+    let mut test_case = TestCase::new()?;
+    test_case.replace("a", "b")?;
+    test_case.replace("b", "a")?;
+
+    test_case.assert_run()?;
+    test_case.assert_renamed()?;
+
+    Ok(())
+}
+
+#[test]
+fn test_rotation() -> anyhow::Result<()> {
+    let mut test_case = TestCase::new()?;
+    test_case.replace("1", "2")?;
+    test_case.replace("2", "3")?;
+    test_case.replace("3", "1")?;
+
+    test_case.assert_run()?;
+    test_case.assert_renamed()?;
+
+    Ok(())
+}
+
+#[test]
+fn test_nested() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    fs::create_dir_all(dir.path().join("dir/sub"))?;
+    for (file, content) in [("dir/a", "A"), ("dir/b", "B"), ("dir/sub/c", "C")] {
+        fs::write(dir.path().join(file), content)?;
+    }
+
+    let (assert, _) = run_in_dir(
+        dir.path(),
+        &["dir", "dir/a", "dir/b", "dir/sub", "dir/sub/c"],
+        "",
+        "new\ndir/x\ndir/b\ndir/sub2\nnew/sub2/y\n",
+    )?;
+    assert.success();
+    assert!(!dir.path().join("dir").exists());
+    assert_eq!(fs::read_to_string(dir.path().join("new/x"))?, "A");
+    assert_eq!(fs::read_to_string(dir.path().join("new/b"))?, "B");
+    assert_eq!(fs::read_to_string(dir.path().join("new/sub2/y"))?, "C");
+
+    Ok(())
+}
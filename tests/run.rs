@@ -33,6 +33,16 @@ pub fn run_with_env(
     input: &[impl AsRef<str>],
     replacements: &[impl AsRef<str>],
     create_inputs: bool,
+) -> anyhow::Result<assert_cmd::assert::Assert> {
+    let state = tempfile::tempdir().context("Could not create state dir")?;
+    run_with_state(input, replacements, create_inputs, state.path())
+}
+
+pub fn run_with_state(
+    input: &[impl AsRef<str>],
+    replacements: &[impl AsRef<str>],
+    create_inputs: bool,
+    state_dir: &path::Path,
 ) -> anyhow::Result<assert_cmd::assert::Assert> {
     let input: Vec<_> = input.iter().map(AsRef::as_ref).collect();
     let replacements: Vec<_> = replacements.iter().map(AsRef::as_ref).collect();
@@ -53,6 +63,7 @@ pub fn run_with_env(
     let assert = renamer("env-editor.py")?
         .env("TEST_EDITOR_INPUT", &input_file)
         .env("TEST_EDITOR_OUTPUT", &output_file)
+        .env("XDG_STATE_HOME", state_dir)
         .current_dir(tempdir.path())
         .write_stdin(input.join("\n"))
         .assert();
@@ -68,20 +79,119 @@ pub fn run_with_env(
     Ok(assert)
 }
 
+/// Whether `program` is installed, saying that the test is skipped if not.
+pub fn installed(program: &str) -> bool {
+    let found = std::process::Command::new(program)
+        .arg("--version")
+        .output()
+        .is_ok();
+    if !found {
+        eprintln!("skipping test: {} is not installed", program);
+    }
+    found
+}
+
+/// Run `program` in `dir`, failing unless it succeeds, and return its output.
+pub fn tool(dir: &path::Path, program: &str, args: &[&str]) -> anyhow::Result<String> {
+    let output = std::process::Command::new(program)
+        .args(args)
+        .current_dir(dir)
+        .output()?;
+    anyhow::ensure!(
+        output.status.success(),
+        "{} {:?} failed: {}",
+        program,
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Run git in `dir` with a test identity, returning its output.
+pub fn git(dir: &path::Path, args: &[&str]) -> anyhow::Result<String> {
+    let output = std::process::Command::new("git")
+        .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+        .args(args)
+        .current_dir(dir)
+        .output()?;
+    anyhow::ensure!(output.status.success(), "git {:?} failed", args);
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Run renamer in `dir` with extra arguments and raw stdin, returning what the
+/// editor was given.
+pub fn run_in_dir(
+    dir: &path::Path,
+    args: &[&str],
+    stdin: impl Into<Vec<u8>>,
+    replacements: &str,
+) -> anyhow::Result<(assert_cmd::assert::Assert, String)> {
+    let state = tempfile::tempdir().context("Could not create state dir")?;
+    run_in_dir_with_state(dir, state.path(), args, stdin, replacements)
+}
+
+/// Like `run_in_dir`, but keeping the history in `state_dir`.
+pub fn run_in_dir_with_state(
+    dir: &path::Path,
+    state_dir: &path::Path,
+    args: &[&str],
+    stdin: impl Into<Vec<u8>>,
+    replacements: &str,
+) -> anyhow::Result<(assert_cmd::assert::Assert, String)> {
+    let tempdir = tempfile::tempdir().context("Could not create tempdir")?;
+    let input_file = tempdir.path().join("input");
+    let output_file = tempdir.path().join("output");
+    fs::write(&output_file, replacements)
+        .context("Could not write replacements to editor output file")?;
+
+    let assert = renamer("env-editor.py")?
+        .args(args)
+        .env("TEST_EDITOR_INPUT", &input_file)
+        .env("TEST_EDITOR_OUTPUT", &output_file)
+        .env("XDG_STATE_HOME", state_dir)
+        .current_dir(dir)
+        .write_stdin(stdin)
+        .assert();
+
+    let editor_input = fs::read_to_string(&input_file).unwrap_or_default();
+    Ok((assert, editor_input))
+}
+
 pub struct TestCase {
     dir: tempfile::TempDir,
+    state: tempfile::TempDir,
     replacements: Vec<(String, String)>,
 }
 
 impl TestCase {
     pub fn new() -> anyhow::Result<TestCase> {
         let dir = tempfile::tempdir().context("Could not create tempdir")?;
+        let state = tempfile::tempdir().context("Could not create state dir")?;
         Ok(TestCase {
             dir,
+            state,
             replacements: Vec::new(),
         })
     }
 
+    /// Run renamer with the given arguments and without input, sharing the
+    /// state directory with previous runs of this test case.
+    pub fn run_args(&self, args: &[&str]) -> anyhow::Result<assert_cmd::assert::Assert> {
+        self.run_args_in(self.dir.path(), args)
+    }
+
+    pub fn run_args_in(
+        &self,
+        cwd: &path::Path,
+        args: &[&str],
+    ) -> anyhow::Result<assert_cmd::assert::Assert> {
+        Ok(renamer("env-editor.py")?
+            .args(args)
+            .env("XDG_STATE_HOME", self.state.path())
+            .current_dir(cwd)
+            .assert())
+    }
+
     pub fn replace(
         &mut self,
         old: impl Into<String>,
@@ -98,6 +208,11 @@ impl TestCase {
         Ok(())
     }
 
+    /// Create a file that is not part of the replacements.
+    pub fn create(&self, name: impl AsRef<path::Path>) -> anyhow::Result<()> {
+        fs::write(self.dir.path().join(name), "").context("Could not create test case file")
+    }
+
     pub fn input(&self) -> anyhow::Result<Vec<String>> {
         self.replacements
             .iter()
@@ -123,7 +238,12 @@ impl TestCase {
     }
 
     pub fn run(&self) -> anyhow::Result<assert_cmd::assert::Assert> {
-        run_with_env(&self.input()?, &self.replacements()?, false)
+        run_with_state(
+            &self.input()?,
+            &self.replacements()?,
+            false,
+            self.state.path(),
+        )
     }
 
     pub fn assert_run(&self) -> anyhow::Result<assert_cmd::assert::Assert> {
@@ -151,6 +271,24 @@ impl TestCase {
         Ok(())
     }
 
+    pub fn assert_unchanged(&self) -> anyhow::Result<()> {
+        let actual_files = self.get_files()?;
+        let expected_files: collections::HashSet<_> =
+            self.replacements.iter().map(|(s, _)| s).collect();
+        assert_eq!(
+            expected_files,
+            actual_files.iter().collect::<collections::HashSet<_>>()
+        );
+
+        for (old, _) in &self.replacements {
+            let content = fs::read_to_string(self.dir.path().join(old))
+                .context("Could not read input file")?;
+            assert_eq!(old, &content, "File {} has unexpected content", &old);
+        }
+
+        Ok(())
+    }
+
     fn get_files(&self) -> anyhow::Result<Vec<String>> {
         let mut files = Vec::new();
         for file in self
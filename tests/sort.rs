@@ -0,0 +1,45 @@
+mod run;
+
+use std::fs;
+
+use run::run_in_dir;
+
+#[test]
+fn test_sort_natural_by_default() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    for file in ["file10", "file2", "file1"] {
+        fs::write(dir.path().join(file), "")?;
+    }
+
+    let (_, editor_input) = run_in_dir(dir.path(), &["."], "", "")?;
+    assert_eq!(editor_input, "./file1\n./file2\n./file10");
+
+    let (_, editor_input) = run_in_dir(dir.path(), &["--reverse", "."], "", "")?;
+    assert_eq!(editor_input, "./file10\n./file2\n./file1");
+
+    Ok(())
+}
+
+#[test]
+fn test_sort_keys() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    fs::write(dir.path().join("b.txt"), "123")?;
+    fs::write(dir.path().join("a.md"), "12")?;
+    fs::write(dir.path().join("c.rs"), "1")?;
+    let input = "b.txt\na.md\nc.rs\n";
+
+    let (_, editor_input) = run_in_dir(dir.path(), &[], input, "")?;
+    assert_eq!(editor_input, "b.txt\na.md\nc.rs");
+
+    let (_, editor_input) = run_in_dir(dir.path(), &["--sort", "name"], input, "")?;
+    assert_eq!(editor_input, "a.md\nb.txt\nc.rs");
+
+    let (_, editor_input) = run_in_dir(dir.path(), &["--sort", "size"], input, "")?;
+    assert_eq!(editor_input, "c.rs\na.md\nb.txt");
+
+    let args = ["--sort", "ext", "--reverse"];
+    let (_, editor_input) = run_in_dir(dir.path(), &args, input, "")?;
+    assert_eq!(editor_input, "b.txt\nc.rs\na.md");
+
+    Ok(())
+}
@@ -0,0 +1,132 @@
+mod run;
+
+use predicates::prelude::*;
+use run::{run_in_dir_with_state, TestCase};
+
+#[test]
+fn test_undo() -> anyhow::Result<()> {
+    let mut test_case = TestCase::new()?;
+    test_case.replace("1", "2")?;
+    test_case.replace("3", "4")?;
+
+    test_case.assert_run()?;
+    test_case.assert_renamed()?;
+
+    test_case
+        .run_args(&["--undo"])?
+        .success()
+        .stdout("Restored 2 files.\n");
+    test_case.assert_unchanged()?;
+
+    Ok(())
+}
+
+#[test]
+fn test_redo() -> anyhow::Result<()> {
+    let mut test_case = TestCase::new()?;
+    test_case.replace("a", "b")?;
+    test_case.replace("b", "a")?;
+
+    test_case.assert_run()?;
+    test_case.run_args(&["--undo"])?.success();
+    test_case.assert_unchanged()?;
+
+    test_case
+        .run_args(&["--redo"])?
+        .success()
+        .stdout("Renamed 2 files.\n");
+    test_case.assert_renamed()?;
+
+    test_case
+        .run_args(&["--redo"])?
+        .failure()
+        .stderr("Error: No redo information found.\n");
+
+    Ok(())
+}
+
+#[test]
+fn test_undo_by_id() -> anyhow::Result<()> {
+    let mut test_case = TestCase::new()?;
+    test_case.replace("1", "2")?;
+
+    test_case.assert_run()?;
+    test_case
+        .run_args(&["--undo", "2"])?
+        .failure()
+        .stderr("Error: Operation 2 not found in history.\n");
+    test_case.run_args(&["--undo", "1"])?.success();
+    test_case.assert_unchanged()?;
+    test_case
+        .run_args(&["--undo"])?
+        .failure()
+        .stderr("Error: No undo information found.\n");
+
+    Ok(())
+}
+
+#[test]
+fn test_history() -> anyhow::Result<()> {
+    let mut test_case = TestCase::new()?;
+    test_case.replace("1", "2")?;
+
+    test_case
+        .run_args(&["--history"])?
+        .success()
+        .stdout("No renaming operations recorded.\n");
+
+    test_case.assert_run()?;
+    test_case
+        .run_args(&["--history"])?
+        .success()
+        .stdout(predicate::str::is_match(r"^   1  \S+      1 files  ")?);
+
+    Ok(())
+}
+
+#[test]
+fn test_undo_other_dir() -> anyhow::Result<()> {
+    let mut test_case = TestCase::new()?;
+    test_case.replace("1", "2")?;
+
+    test_case.assert_run()?;
+
+    let other_dir = tempfile::tempdir()?;
+    test_case
+        .run_args_in(other_dir.path(), &["--undo"])?
+        .failure()
+        .stderr(predicate::str::starts_with(
+            "Error: No undo information found for the current directory.",
+        ));
+    test_case
+        .run_args_in(other_dir.path(), &["--history"])?
+        .success()
+        .stdout("No renaming operations recorded.\n");
+
+    test_case
+        .run_args_in(other_dir.path(), &["--undo", "--any-dir"])?
+        .success();
+    test_case.assert_unchanged()?;
+
+    Ok(())
+}
+
+#[test]
+fn test_corrupt_history() -> anyhow::Result<()> {
+    let (dir, state) = (tempfile::tempdir()?, tempfile::tempdir()?);
+    std::fs::create_dir(state.path().join("pipe-rename"))?;
+    std::fs::write(state.path().join("pipe-rename/history.json"), "garbage")?;
+    std::fs::write(dir.path().join("a"), "")?;
+
+    // Renaming works without the history, undo does not.
+    let (assert, _) = run_in_dir_with_state(dir.path(), state.path(), &["a"], "", "b\n")?;
+    assert
+        .success()
+        .stderr(predicate::str::contains("Warning: could not record"));
+    assert!(dir.path().join("b").exists());
+
+    let (assert, _) = run_in_dir_with_state(dir.path(), state.path(), &["--undo"], "", "")?;
+    assert.failure();
+
+    Ok(())
+}